@@ -0,0 +1,120 @@
+//! Compares the BVH against a linear scan over every primitive, for the
+//! same set of camera rays through a scene of a few thousand spheres.
+//!
+//! Run with `cargo bench --bench bvh`.
+
+use glam::DVec3;
+use render_3d::bvh::Bvh;
+use render_3d::material::Material;
+use render_3d::raytracer::{Primitive, Sphere};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SPHERE_COUNT: usize = 5000;
+const RAYS_PER_SIDE: i32 = 128;
+
+/// A small linear congruential generator, so every run places the spheres
+/// in the same spots without pulling in a dependency.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn random_spheres(count: usize) -> Vec<Primitive> {
+    let mut rng = Lcg(42);
+    (0..count)
+        .map(|_| {
+            Primitive::Sphere(Sphere {
+                center: DVec3::new(
+                    rng.next() * 40.0 - 20.0,
+                    rng.next() * 40.0 - 20.0,
+                    rng.next() * 40.0 + 5.0,
+                ),
+                radius: rng.next() * 0.3 + 0.05,
+                material: Material::new(DVec3::splat(255.0)),
+            })
+        })
+        .collect()
+}
+
+fn camera_rays() -> Vec<DVec3> {
+    let half = RAYS_PER_SIDE / 2;
+    (-half..half)
+        .flat_map(|y| {
+            (-half..half).map(move |x| {
+                DVec3::new(
+                    x as f64 / RAYS_PER_SIDE as f64,
+                    y as f64 / RAYS_PER_SIDE as f64,
+                    1.0,
+                )
+            })
+        })
+        .collect()
+}
+
+fn linear_closest(primitives: &[Primitive], direction: DVec3) -> Option<f64> {
+    primitives
+        .iter()
+        .filter_map(|p| p.intersect(DVec3::ZERO, direction, 1.0, f64::INFINITY))
+        .reduce(f64::min)
+}
+
+fn time(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    let start = Instant::now();
+    let hits = f();
+    (start.elapsed(), hits)
+}
+
+fn main() {
+    let primitives = random_spheres(SPHERE_COUNT);
+    let rays = camera_rays();
+
+    let (build_time, bvh) = {
+        let start = Instant::now();
+        let bvh = Bvh::build(&primitives);
+        (start.elapsed(), bvh)
+    };
+
+    let (linear_time, linear_hits) = time(|| {
+        rays.iter()
+            .filter(|d| black_box(linear_closest(&primitives, **d)).is_some())
+            .count()
+    });
+    let (bvh_time, bvh_hits) = time(|| {
+        rays.iter()
+            .filter(|d| {
+                black_box(bvh.closest_intersection(
+                    &primitives,
+                    DVec3::ZERO,
+                    **d,
+                    1.0,
+                    f64::INFINITY,
+                ))
+                .is_some()
+            })
+            .count()
+    });
+
+    assert_eq!(linear_hits, bvh_hits, "the BVH and linear scan disagree");
+
+    println!(
+        "{} spheres, {} rays ({} hits)",
+        SPHERE_COUNT,
+        rays.len(),
+        bvh_hits
+    );
+    println!("BVH build:   {:>10.2?}", build_time);
+    println!("linear scan: {:>10.2?}", linear_time);
+    println!("BVH:         {:>10.2?}", bvh_time);
+    println!(
+        "speedup:     {:>9.1}x",
+        linear_time.as_secs_f64() / bvh_time.as_secs_f64()
+    );
+}
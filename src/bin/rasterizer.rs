@@ -1,16 +1,6 @@
-use render_3d::canvas::Canvas;
-use render_3d::scene::Scene;
+use render_3d::cli::{self, Renderer};
+use std::process::ExitCode;
 
-fn main() {
-    let mut canvas = Canvas::new(512, 512);
-
-    let scene: Scene = serde_yaml::from_slice(&std::fs::read("cube.yaml").unwrap()).unwrap();
-
-    scene
-        .transform()
-        .cull_back_faces()
-        .clip()
-        .render(&mut canvas);
-
-    canvas.save("raster.png").unwrap();
+fn main() -> ExitCode {
+    cli::main(Renderer::Rasterizer)
 }
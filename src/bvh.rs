@@ -0,0 +1,465 @@
+use crate::raytracer::Primitive;
+use glam::DVec3;
+
+/// How many primitives a leaf may hold before we try to split it.
+const MAX_LEAF_SIZE: usize = 4;
+/// How many buckets the surface area heuristic sorts centroids into.
+const SAH_BUCKETS: usize = 12;
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: DVec3,
+    pub max: DVec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self {
+            min: DVec3::splat(f64::INFINITY),
+            max: DVec3::splat(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn include(&self, point: DVec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn centroid(&self) -> DVec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    fn surface_area(&self) -> f64 {
+        let extent = self.max - self.min;
+        if extent.min_element() < 0.0 {
+            return 0.0;
+        }
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    /// The entry distance of the ray into the box using the slab method, or
+    /// `None` if it misses the box within `min_t..=max_t`.
+    fn intersect_ray(
+        &self,
+        origin: DVec3,
+        inverse_direction: DVec3,
+        min_t: f64,
+        max_t: f64,
+    ) -> Option<f64> {
+        let mut t_near = min_t;
+        let mut t_far = max_t;
+        for axis in 0..3 {
+            if inverse_direction[axis].is_infinite() {
+                // The ray runs parallel to this slab, and would make NaNs
+                // below if it starts on one of its faces, so check it's
+                // inside directly.
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+
+        (t_near <= t_far).then_some(t_near)
+    }
+}
+
+impl Primitive {
+    pub fn bounding_box(&self) -> Aabb {
+        match self {
+            Self::Sphere(sphere) => Aabb {
+                min: sphere.center - DVec3::splat(sphere.radius),
+                max: sphere.center + DVec3::splat(sphere.radius),
+            },
+            Self::Triangle(triangle) => Aabb::empty()
+                .include(triangle.v0)
+                .include(triangle.v1)
+                .include(triangle.v2),
+        }
+    }
+}
+
+/// A candidate split of a node's primitives into the buckets below
+/// `bucket` along `axis` and the rest.
+#[derive(Debug, Clone, Copy)]
+struct Split {
+    axis: usize,
+    axis_min: f64,
+    extent: f64,
+    bucket: usize,
+}
+
+impl Split {
+    fn bucket_of(axis_min: f64, extent: f64, centroid: f64) -> usize {
+        let offset = (centroid - axis_min) / extent;
+        ((offset * SAH_BUCKETS as f64) as usize).min(SAH_BUCKETS - 1)
+    }
+
+    fn goes_left(&self, bounds: &Aabb) -> bool {
+        Self::bucket_of(self.axis_min, self.extent, bounds.centroid()[self.axis]) < self.bucket
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    /// Covers `indices[start..start + count]`.
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        count: usize,
+    },
+    /// The left child always directly follows its parent in `nodes`.
+    Interior { bounds: Aabb, right: usize },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Self::Leaf { bounds, .. } | Self::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a slice of primitives, split with the
+/// surface area heuristic. It stores indices into that slice, so the same
+/// slice has to be passed back in when querying it.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn build(primitives: &[Primitive]) -> Self {
+        let bounds: Vec<Aabb> = primitives.iter().map(Primitive::bounding_box).collect();
+        let mut bvh = Self {
+            nodes: vec![],
+            indices: (0..primitives.len()).collect(),
+        };
+        if !primitives.is_empty() {
+            bvh.build_node(&bounds, 0, primitives.len());
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], start: usize, end: usize) -> usize {
+        let node_index = self.nodes.len();
+        let indices = &mut self.indices[start..end];
+
+        let node_bounds = indices
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+
+        let split = if indices.len() > MAX_LEAF_SIZE {
+            Self::find_split(bounds, indices, &node_bounds)
+        } else {
+            None
+        };
+
+        let mid = match split {
+            Some(split) => {
+                // partition the indices around the chosen bucket boundary
+                let mut mid = 0;
+                for i in 0..indices.len() {
+                    if split.goes_left(&bounds[indices[i]]) {
+                        indices.swap(i, mid);
+                        mid += 1;
+                    }
+                }
+                start + mid
+            }
+            None => {
+                self.nodes.push(Node::Leaf {
+                    bounds: node_bounds,
+                    start,
+                    count: end - start,
+                });
+                return node_index;
+            }
+        };
+
+        self.nodes.push(Node::Interior {
+            bounds: node_bounds,
+            right: 0,
+        });
+        self.build_node(bounds, start, mid);
+        let right_index = self.build_node(bounds, mid, end);
+        self.nodes[node_index] = Node::Interior {
+            bounds: node_bounds,
+            right: right_index,
+        };
+
+        node_index
+    }
+
+    /// Picks the split with the lowest surface area heuristic cost, or `None`
+    /// if no split beats keeping a single leaf.
+    fn find_split(bounds: &[Aabb], indices: &[usize], node_bounds: &Aabb) -> Option<Split> {
+        let centroid_bounds = indices
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.include(bounds[i].centroid()));
+
+        let mut best: Option<(Split, f64)> = None;
+
+        for axis in 0..3 {
+            let axis_min = centroid_bounds.min[axis];
+            let extent = centroid_bounds.max[axis] - axis_min;
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut buckets = [(0usize, Aabb::empty()); SAH_BUCKETS];
+            for &i in indices {
+                let b = Split::bucket_of(axis_min, extent, bounds[i].centroid()[axis]);
+                buckets[b].0 += 1;
+                buckets[b].1 = buckets[b].1.union(&bounds[i]);
+            }
+
+            for bucket in 1..SAH_BUCKETS {
+                let (left_count, left_bounds) = buckets[..bucket]
+                    .iter()
+                    .fold((0, Aabb::empty()), |(n, acc), (count, b)| {
+                        (n + count, acc.union(b))
+                    });
+                let (right_count, right_bounds) = buckets[bucket..]
+                    .iter()
+                    .fold((0, Aabb::empty()), |(n, acc), (count, b)| {
+                        (n + count, acc.union(b))
+                    });
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let cost = left_count as f64 * left_bounds.surface_area()
+                    + right_count as f64 * right_bounds.surface_area();
+                if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                    let split = Split {
+                        axis,
+                        axis_min,
+                        extent,
+                        bucket,
+                    };
+                    best = Some((split, cost));
+                }
+            }
+        }
+
+        // Splitting only pays off if it's cheaper than testing every
+        // primitive in one leaf.
+        let leaf_cost = indices.len() as f64 * node_bounds.surface_area();
+        best.filter(|(_, cost)| *cost < leaf_cost)
+            .map(|(split, _)| split)
+    }
+
+    /// The closest primitive hit by the ray within `min_t..=max_t`, with the
+    /// `t` at which it was hit.
+    pub fn closest_intersection<'a>(
+        &self,
+        primitives: &'a [Primitive],
+        origin: DVec3,
+        direction: DVec3,
+        min_t: f64,
+        max_t: f64,
+    ) -> Option<(&'a Primitive, f64)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = direction.recip();
+        let mut closest_t = max_t;
+        let mut closest_primitive = None;
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node
+                .bounds()
+                .intersect_ray(origin, inverse_direction, min_t, closest_t)
+                .is_none()
+            {
+                continue;
+            }
+
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for &i in &self.indices[start..start + count] {
+                        let primitive = &primitives[i];
+                        if let Some(t) = primitive.intersect(origin, direction, min_t, closest_t) {
+                            closest_t = t;
+                            closest_primitive = Some(primitive);
+                        }
+                    }
+                }
+                Node::Interior { right, .. } => {
+                    let left = node_index + 1;
+                    let left_t = self.nodes[left].bounds().intersect_ray(
+                        origin,
+                        inverse_direction,
+                        min_t,
+                        closest_t,
+                    );
+                    let right_t = self.nodes[right].bounds().intersect_ray(
+                        origin,
+                        inverse_direction,
+                        min_t,
+                        closest_t,
+                    );
+                    // visit the nearer child first so `closest_t` shrinks
+                    // as early as possible
+                    match (left_t, right_t) {
+                        (Some(l), Some(r)) if r < l => stack.extend([left, right]),
+                        (Some(_), Some(_)) => stack.extend([right, left]),
+                        (Some(_), None) => stack.push(left),
+                        (None, Some(_)) => stack.push(right),
+                        (None, None) => {}
+                    }
+                }
+            }
+        }
+
+        closest_primitive.map(|primitive| (primitive, closest_t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::raytracer::Sphere;
+    use crate::scene::Triangle;
+    use glam::DVec2;
+
+    // A small linear congruential generator, like the one in the benchmark.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        // a whole number of halves in `0..=max`
+        fn half_step(&mut self, max: f64) -> f64 {
+            (self.next() * (max * 2.0 + 1.0)).floor() / 2.0
+        }
+    }
+
+    fn triangle(v0: DVec3, v1: DVec3, v2: DVec3) -> Primitive {
+        Primitive::Triangle(Triangle {
+            v0,
+            v1,
+            v2,
+            normals: [DVec3::Z; 3],
+            uvs: [DVec2::ZERO; 3],
+            colors: [DVec3::ONE; 3],
+            edges: [true; 3],
+            material: Material::new(DVec3::ONE),
+            center: (v0 + v1 + v2) / 3.0,
+        })
+    }
+
+    // Squares facing along each axis, with their corners on a grid of halves
+    // so that lots of them share the planes of their bounding boxes' faces,
+    // and some spheres in between.
+    fn primitives() -> Vec<Primitive> {
+        let mut rng = Lcg(7);
+        let mut primitives = vec![];
+        for i in 0..300 {
+            let corner = DVec3::new(rng.half_step(8.0), rng.half_step(8.0), rng.half_step(8.0));
+            let axis = i % 3;
+            let (u, v) = (
+                DVec3::AXES[(axis + 1) % 3] * (rng.half_step(1.5) + 0.5),
+                DVec3::AXES[(axis + 2) % 3] * (rng.half_step(1.5) + 0.5),
+            );
+            primitives.push(triangle(corner, corner + u, corner + u + v));
+            primitives.push(triangle(corner, corner + u + v, corner + v));
+        }
+        for _ in 0..50 {
+            primitives.push(Primitive::Sphere(Sphere {
+                center: DVec3::new(rng.next(), rng.next(), rng.next()) * 9.0,
+                radius: rng.next() * 0.5 + 0.1,
+                material: Material::new(DVec3::ONE),
+            }));
+        }
+        primitives
+    }
+
+    fn linear_closest(primitives: &[Primitive], origin: DVec3, direction: DVec3) -> Option<f64> {
+        primitives
+            .iter()
+            .filter_map(|p| p.intersect(origin, direction, 0.001, f64::INFINITY))
+            .reduce(f64::min)
+    }
+
+    fn assert_same_hits(primitives: &[Primitive], bvh: &Bvh, rays: &[(DVec3, DVec3)]) {
+        let mut hits = 0;
+        for &(origin, direction) in rays {
+            let expected = linear_closest(primitives, origin, direction);
+            let found = bvh
+                .closest_intersection(primitives, origin, direction, 0.001, f64::INFINITY)
+                .map(|(_, t)| t);
+            assert_eq!(found, expected, "{:?} {:?}", origin, direction);
+            hits += expected.is_some() as usize;
+        }
+        // make sure the rays aren't all missing everything
+        assert!(hits > rays.len() / 10, "{} of {}", hits, rays.len());
+    }
+
+    #[test]
+    fn finds_the_same_hits_as_a_linear_scan() {
+        let primitives = primitives();
+        let bvh = Bvh::build(&primitives);
+        let mut rng = Lcg(11);
+        let rays: Vec<(DVec3, DVec3)> = (0..2000)
+            .map(|_| {
+                let origin = DVec3::new(rng.next(), rng.next(), rng.next()) * 12.0 - 1.5;
+                let direction = DVec3::new(rng.next(), rng.next(), rng.next()) * 2.0 - 1.0;
+                (origin, direction)
+            })
+            .collect();
+        assert_same_hits(&primitives, &bvh, &rays);
+    }
+
+    #[test]
+    fn finds_hits_for_rays_along_bounding_box_faces() {
+        let primitives = primitives();
+        let bvh = Bvh::build(&primitives);
+        // Rays along each axis from every point on the grid of halves, which
+        // start on the planes of many boxes' faces and run along them.
+        let mut rays = vec![];
+        for axis in 0..3 {
+            for direction in [DVec3::AXES[axis], -DVec3::AXES[axis]] {
+                for a in 0..=20 {
+                    for b in 0..=20 {
+                        let mut origin = DVec3::splat(if direction.max_element() > 0.0 {
+                            -1.0
+                        } else {
+                            11.0
+                        });
+                        origin[(axis + 1) % 3] = a as f64 / 2.0;
+                        origin[(axis + 2) % 3] = b as f64 / 2.0;
+                        rays.push((origin, direction));
+                    }
+                }
+            }
+        }
+        assert_same_hits(&primitives, &bvh, &rays);
+    }
+}
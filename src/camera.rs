@@ -1,13 +1,23 @@
-use glam::{DVec3, IVec2};
+use glam::{DMat4, DQuat, DVec2, DVec3, DVec4};
 use serde::Deserialize;
 
 use crate::canvas::Canvas;
 
+/// The window the camera looks through, `distance` in front of it and
+/// facing it. Together they make the frustum of what the camera sees.
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub struct Viewport {
     width: f64,
     height: f64,
     pub distance: f64,
+    /// Where the middle of the viewport is, off the line the camera looks
+    /// along, for an asymmetric frustum.
+    #[serde(default)]
+    pub offset: DVec2,
+    /// How far in front of the camera things can be and still be seen, or
+    /// `None` for no limit.
+    #[serde(default)]
+    pub far: Option<f64>,
 }
 
 impl Default for Viewport {
@@ -16,37 +26,153 @@ impl Default for Viewport {
             width: 1.0,
             height: 1.0,
             distance: 1.0,
+            offset: DVec2::ZERO,
+            far: None,
         }
     }
 }
 
 impl Viewport {
+    /// The bottom left and top right corners of the viewport, in the plane
+    /// it's in.
+    pub fn corners(&self) -> [DVec2; 2] {
+        let half_size = DVec2::new(self.width, self.height) / 2.0;
+        [self.offset - half_size, self.offset + half_size]
+    }
+
     pub fn direction_from_canvas(&self, canvas: &Canvas, cx: i32, cy: i32) -> DVec3 {
+        self.direction_from_canvas_point(canvas, cx as f64, cy as f64)
+    }
+
+    /// Like `direction_from_canvas`, but through any point on the canvas
+    /// rather than just whole pixels.
+    pub fn direction_from_canvas_point(&self, canvas: &Canvas, x: f64, y: f64) -> DVec3 {
         DVec3::new(
-            cx as f64 * (self.width) / (canvas.width() as f64),
-            cy as f64 * (self.height) / (canvas.height() as f64),
+            x * (self.width) / (canvas.width() as f64) + self.offset.x,
+            y * (self.height) / (canvas.height() as f64) + self.offset.y,
             self.distance,
         )
     }
+}
 
-    pub fn canvas_from_viewport(&self, canvas: &Canvas, x: f64, y: f64) -> IVec2 {
-        IVec2::new(
-            (x * (canvas.width() as f64) / (self.width)).floor() as i32,
-            (y * (canvas.height() as f64) / (self.height)).floor() as i32,
-        )
+/// How the camera projects the scene onto the viewport. The parallel
+/// projections see the viewport's own width and height of the scene, from
+/// the viewport's `distance` out to its `far` plane, which they need.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum Projection {
+    /// Rays spread out from the camera through the viewport, so that things
+    /// look smaller the further away they are.
+    #[default]
+    Perspective,
+    /// Parallel rays straight through the viewport, so that things look the
+    /// same size however far away they are.
+    Orthographic,
+    /// Orthographic, turned 45° about `y` and tilted down until the `x`,
+    /// `y` and `z` axes are all foreshortened the same. The camera looks
+    /// along `+x`, `-y` and `+z`, so it belongs up and back along `-x` and
+    /// `-z` from what it looks at.
+    Isometric,
+    /// Like `Isometric`, but tilted down 30°, so that the `x` and `z` axes
+    /// slope one up for every two across, and `y` is foreshortened less.
+    Dimetric,
+    /// Parallel rays slanting through the viewport, so that what's parallel
+    /// to it keeps its shape, while depth goes off at `angle` degrees
+    /// anticlockwise from `x`, scaled by `depth`.
+    Oblique { angle: f64, depth: f64 },
+    /// Oblique at 45° with depth at half scale.
+    Cabinet,
+    /// Oblique at 45° with depth at full scale.
+    Cavalier,
+}
+
+impl Projection {
+    /// Whether rays are parallel rather than spreading out from the camera.
+    pub fn is_parallel(&self) -> bool {
+        *self != Self::Perspective
     }
 
-    pub fn project_vertex(&self, canvas: &Canvas, vertex: DVec3) -> IVec2 {
-        self.canvas_from_viewport(
-            canvas,
-            vertex[0] * self.distance / vertex[2],
-            vertex[1] * self.distance / vertex[2],
-        )
+    /// How the projection turns the camera, on top of its own transform.
+    pub fn rotation(&self) -> DQuat {
+        let tilted =
+            |angle: f64| DQuat::from_rotation_y(45f64.to_radians()) * DQuat::from_rotation_x(angle);
+        match *self {
+            Self::Isometric => tilted((0.5f64).sqrt().atan()),
+            Self::Dimetric => tilted(30f64.to_radians()),
+            _ => DQuat::IDENTITY,
+        }
+    }
+
+    /// Where the rays come from, in camera space and homogeneous
+    /// coordinates: the camera itself for perspective, or infinitely far
+    /// back along the rays for the parallel projections, as a direction
+    /// whose `z` is -1.
+    pub fn eye(&self) -> DVec4 {
+        if !self.is_parallel() {
+            return DVec4::W;
+        }
+        let (angle, depth) = self.oblique().unwrap_or_default();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        DVec4::new(depth * cos, depth * sin, -1.0, 0.0)
+    }
+
+    // The angle and depth scale of an oblique projection.
+    fn oblique(&self) -> Option<(f64, f64)> {
+        match *self {
+            Self::Oblique { angle, depth } => Some((angle, depth)),
+            Self::Cabinet => Some((45.0, 0.5)),
+            Self::Cavalier => Some((45.0, 1.0)),
+            _ => None,
+        }
+    }
+
+    /// The projection from camera space into clip space through `viewport`,
+    /// or `None` if it's parallel and the viewport has no far plane.
+    pub fn matrix(&self, viewport: &Viewport) -> Option<DMat4> {
+        let (corners, near) = (viewport.corners(), viewport.distance);
+        if !self.is_parallel() {
+            return Some(frustum(corners, near, viewport.far));
+        }
+        // Slant the rays of an oblique projection straight, keeping the
+        // viewport where it is.
+        let eye = self.eye();
+        let shear = DMat4::from_cols(
+            DVec4::X,
+            DVec4::Y,
+            DVec4::new(eye.x, eye.y, 1.0, 0.0),
+            DVec4::new(-eye.x * near, -eye.y * near, 0.0, 1.0),
+        );
+        Some(orthographic(corners, near, viewport.far?) * shear)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
-pub struct Camera {
-    pub viewport: Viewport,
-    pub position: DVec3,
+/// The perspective projection from camera space into clip space of the
+/// frustum through the rectangle `min..max` on the plane at `near`, out to
+/// `far` or forever. It's OpenGL's `glFrustum`, but for a camera looking
+/// along `+z`: everything seen ends up within `-w..=w` on every axis, with
+/// `z` going from `-w` at the near plane to `w` at the far one.
+pub fn frustum([min, max]: [DVec2; 2], near: f64, far: Option<f64>) -> DMat4 {
+    let size = max - min;
+    let (a, b) = match far {
+        Some(far) => (
+            (far + near) / (far - near),
+            -2.0 * far * near / (far - near),
+        ),
+        // the limit as the far plane goes to infinity
+        None => (1.0, -2.0 * near),
+    };
+    DMat4::from_cols(
+        DVec4::new(2.0 * near / size.x, 0.0, 0.0, 0.0),
+        DVec4::new(0.0, 2.0 * near / size.y, 0.0, 0.0),
+        DVec4::new(-(max.x + min.x) / size.x, -(max.y + min.y) / size.y, a, 1.0),
+        DVec4::new(0.0, 0.0, b, 0.0),
+    )
+}
+
+/// The orthographic projection from camera space into clip space of the box
+/// over the rectangle `min..max` from `near` to `far` along `z`, which is
+/// OpenGL's `glOrtho` for a camera looking along `+z` like `frustum`.
+pub fn orthographic([min, max]: [DVec2; 2], near: f64, far: f64) -> DMat4 {
+    let size = (max - min).extend(far - near);
+    let center = ((max + min) / 2.0).extend((far + near) / 2.0);
+    DMat4::from_scale(2.0 / size) * DMat4::from_translation(-center)
 }
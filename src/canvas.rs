@@ -1,65 +1,258 @@
+use crate::line::{self, LineStyle};
 use crate::utils::{interpolate, map_triangle_attribute};
-use glam::{DVec3, IVec2};
+use clap::ValueEnum;
+use glam::{DVec2, DVec3};
 use image::{ImageBuffer, RgbImage};
+use std::array;
 use std::mem;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// How many bands each worker thread gets on average in
+/// `Canvas::draw_in_parallel`. Bands are handed out as threads free up, so
+/// a few per thread keeps cheap bands (all background, say) from leaving
+/// threads idle.
+const BANDS_PER_THREAD: usize = 4;
+
+/// How far in front of a surface, as a fraction of its depth, a
+/// depth-tested line may lie and still count as on it. Lines along the
+/// edges of triangles land on pixels that the triangles' own depths were
+/// rounded differently for, so they need some slack to not flicker.
+const LINE_DEPTH_BIAS: f64 = 0.01;
+
+/// How many samples of each pixel the canvas keeps, for multisample
+/// anti-aliasing. Triangles are tested for coverage and depth at every
+/// sample but only shaded once per pixel, and the samples are averaged into
+/// the final image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Multisampling {
+    /// One sample, in the middle of the pixel.
+    #[default]
+    Off,
+    #[value(name = "2x")]
+    X2,
+    #[value(name = "4x")]
+    X4,
+    #[value(name = "8x")]
+    X8,
+}
+
+impl Multisampling {
+    pub fn samples(&self) -> usize {
+        self.offsets().len()
+    }
+
+    /// Where each sample is from the middle of the pixel. These are the
+    /// usual rotated grid patterns, in sixteenths of a pixel, which spread
+    /// the samples out across both rows and columns so that edges close to
+    /// horizontal or vertical get as many steps as any other.
+    fn offsets(&self) -> &'static [[i8; 2]] {
+        match self {
+            Self::Off => &[[0, 0]],
+            Self::X2 => &[[4, 4], [-4, -4]],
+            Self::X4 => &[[-2, 6], [6, 2], [-6, -2], [2, -6]],
+            Self::X8 => &[
+                [1, 3],
+                [-1, -3],
+                [5, -1],
+                [-3, 5],
+                [-5, -5],
+                [-7, 1],
+                [3, -7],
+                [7, 7],
+            ],
+        }
+    }
+}
+
+/// A pixel of a triangle being drawn by `Canvas::draw_shaded_depth_triangle`.
+#[derive(Debug, Clone, Copy)]
+pub struct Fragment<const N: usize> {
+    pub x: i32,
+    pub y: i32,
+    /// What the depth buffer holds for it.
+    pub depth: f64,
+    pub attributes: [f64; N],
+    /// How fast each attribute changes per pixel to the right.
+    pub d_dx: [f64; N],
+    /// How fast each attribute changes per pixel upwards.
+    pub d_dy: [f64; N],
+}
+
+impl<const N: usize> Fragment<N> {
+    // The fragment at `x`, `y` with `attributes`, where `1 / w` is
+    // `inverse_w`, given the gradients over the screen of `1 / w` and of
+    // each `attribute / w`.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        x: i32,
+        y: i32,
+        depth: f64,
+        inverse_w: f64,
+        attributes: [f64; N],
+        inverse_w_gradient: [f64; 2],
+        attribute_gradients: [[f64; 2]; N],
+    ) -> Self {
+        // the quotient rule, for attribute = (attribute / w) / (1 / w)
+        let derivative = |axis: usize| {
+            array::from_fn(|k| {
+                (attribute_gradients[k][axis] - attributes[k] * inverse_w_gradient[axis])
+                    / inverse_w
+            })
+        };
+        Self {
+            x,
+            y,
+            depth,
+            attributes,
+            d_dx: derivative(0),
+            d_dy: derivative(1),
+        }
+    }
+}
+
+// The gradient over the screen of the plane through `values` at `points`,
+// or zero if the points are in a line.
+fn screen_gradient(points: [DVec2; 3], values: [f64; 3]) -> [f64; 2] {
+    let [p0, p1, p2] = points;
+    let (e1, e2) = (p1 - p0, p2 - p0);
+    let determinant = e1.perp_dot(e2);
+    if determinant == 0.0 {
+        return [0.0, 0.0];
+    }
+    let (d1, d2) = (values[1] - values[0], values[2] - values[0]);
+    [
+        (d1 * e2.y - d2 * e1.y) / determinant,
+        (d2 * e1.x - d1 * e2.x) / determinant,
+    ]
+}
 
 #[derive(Debug, Clone)]
 pub struct Canvas {
-    image: RgbImage,
     width: u32,
     half_width: i32,
     height: u32,
     half_height: i32,
+    multisampling: Multisampling,
+    // The color and depth of every sample, `multisampling.samples()` of
+    // them for each pixel, with the pixels a row at a time from the top.
+    colors: Vec<[u8; 3]>,
     depth_buffer: Vec<f64>,
+    // A canvas split off by `Canvas::split_bands` only holds the image rows
+    // `first_row..first_row + rows`, but keeps the full canvas dimensions so
+    // that canvas coordinates mean the same thing in every band.
+    first_row: u32,
+    rows: u32,
 }
 
 impl Canvas {
     pub fn new(width: u32, height: u32) -> Self {
+        Self::with_multisampling(width, height, Multisampling::Off)
+    }
+
+    pub fn with_multisampling(width: u32, height: u32, multisampling: Multisampling) -> Self {
+        let samples = (width * height) as usize * multisampling.samples();
         Self {
-            image: ImageBuffer::new(width, height),
             width,
             half_width: (width / 2) as i32,
             height,
             half_height: (height / 2) as i32,
-            depth_buffer: vec![0.0; (width * height) as usize],
+            multisampling,
+            colors: vec![[0; 3]; samples],
+            depth_buffer: vec![0.0; samples],
+            first_row: 0,
+            rows: height,
         }
     }
 
-    pub fn put_pixel(&mut self, cx: i32, cy: i32, color: DVec3) {
+    // Maps canvas coordinates to a pixel in this canvas's image, if it's
+    // one of ours.
+    fn image_coordinates(&self, cx: i32, cy: i32) -> Option<(u32, u32)> {
         let x = self.half_width + cx;
         if x < 0 || x >= self.width as i32 {
-            return;
+            return None;
         }
-        let y = self.half_height - cy - 1;
-        if y < 0 || y >= self.height as i32 {
-            return;
+        let y = self.half_height - cy - 1 - self.first_row as i32;
+        if y < 0 || y >= self.rows as i32 {
+            return None;
         }
-        let color_data = color
+        Some((x as u32, y as u32))
+    }
+
+    // The indices in `colors` and `depth_buffer` of the samples of the image
+    // pixel at `x`, `y`.
+    fn samples(&self, x: u32, y: u32) -> Range<usize> {
+        let count = self.multisampling.samples();
+        let first = (x + self.width * y) as usize * count;
+        first..first + count
+    }
+
+    fn set_sample(&mut self, sample: usize, color: DVec3) {
+        self.colors[sample] = color
             .clamp(DVec3::splat(0.0), DVec3::splat(255.0))
             .to_array()
             .map(|f| f as u8);
-        self.image.put_pixel(x as u32, y as u32, color_data.into());
     }
 
-    pub fn put_depth_pixel(&mut self, cx: i32, cy: i32, depth: f64, color: DVec3) {
-        let x = self.half_width + cx;
-        if x < 0 || x >= self.width as i32 {
-            return;
+    // Mixes `color` into the sample, `coverage` of the way from what's there
+    // already.
+    fn blend_sample(&mut self, sample: usize, color: DVec3, coverage: f64) {
+        let [r, g, b] = self.colors[sample];
+        let under = DVec3::new(r as f64, g as f64, b as f64);
+        let color = color.clamp(DVec3::splat(0.0), DVec3::splat(255.0));
+        self.set_sample(sample, under.lerp(color, coverage.clamp(0.0, 1.0)).round());
+    }
+
+    fn set_image_pixel(&mut self, x: u32, y: u32, color: DVec3) {
+        for sample in self.samples(x, y) {
+            self.set_sample(sample, color);
         }
-        let y = self.half_height - cy - 1;
-        if y < 0 || y >= self.height as i32 {
-            return;
+    }
+
+    // Records `depth` in the depth buffer if nothing drawn so far is in front
+    // of it at the sample.
+    fn depth_test(&mut self, sample: usize, depth: f64) -> bool {
+        if depth < self.depth_buffer[sample] {
+            return false;
         }
-        let buffer_index = (x as u32 + self.width * (y as u32)) as usize;
-        if depth < self.depth_buffer[buffer_index] {
-            return;
+        self.depth_buffer[sample] = depth;
+        true
+    }
+
+    // Whether a line at `depth` would be seen at the sample over what's been
+    // drawn so far. Lines don't write depth, so that they never hide each
+    // other.
+    fn line_depth_test(&self, sample: usize, depth: f64) -> bool {
+        depth >= self.depth_buffer[sample] * (1.0 - LINE_DEPTH_BIAS)
+    }
+
+    /// What the depth buffer holds at `cx`, `cy`: the depth of the nearest
+    /// thing drawn there, or zero if nothing has been. Depths are bigger
+    /// the nearer things are.
+    pub fn depth(&self, cx: i32, cy: i32) -> Option<f64> {
+        let (x, y) = self.image_coordinates(cx, cy)?;
+        Some(
+            self.depth_buffer[self.samples(x, y)]
+                .iter()
+                .fold(0.0, |a, &b| b.max(a)),
+        )
+    }
+
+    pub fn put_pixel(&mut self, cx: i32, cy: i32, color: DVec3) {
+        if let Some((x, y)) = self.image_coordinates(cx, cy) {
+            self.set_image_pixel(x, y, color);
+        }
+    }
+
+    /// Sets every pixel to `color`, leaving depth alone.
+    pub fn fill(&mut self, color: DVec3) {
+        for y in 0..self.rows {
+            for x in 0..self.width {
+                self.set_image_pixel(x, y, color);
+            }
         }
-        self.depth_buffer[buffer_index] = depth;
-        let color_data = color
-            .clamp(DVec3::splat(0.0), DVec3::splat(255.0))
-            .to_array()
-            .map(|f| f as u8);
-        self.image.put_pixel(x as u32, y as u32, color_data.into());
     }
 
     pub fn width(&self) -> u32 {
@@ -70,50 +263,192 @@ impl Canvas {
         self.height
     }
 
-    pub fn draw_line(&mut self, p0: IVec2, p1: IVec2, color: DVec3) {
-        if (p1[0] - p0[0]).abs() > (p1[1] - p0[1]).abs() {
-            // this line is more horizontal than vertical
-            let (pl, pr) = if p0[0] < p1[0] { (p0, p1) } else { (p1, p0) };
+    pub fn multisampling(&self) -> Multisampling {
+        self.multisampling
+    }
+
+    /// The `cx` canvas coordinates this canvas covers.
+    pub fn x_range(&self) -> Range<i32> {
+        -self.half_width..(self.width as i32 - self.half_width)
+    }
 
-            let ys = interpolate(pl[0], pl[1] as f64, pr[0], pr[1] as f64);
+    /// The `cy` canvas coordinates this canvas covers, which for a band is
+    /// only the rows it holds.
+    pub fn y_range(&self) -> Range<i32> {
+        let top = self.half_height - 1 - self.first_row as i32;
+        (top - self.rows as i32 + 1)..(top + 1)
+    }
+
+    // The indices in `colors` and `depth_buffer` of the samples of `rows`
+    // image rows from `start`.
+    fn row_samples(&self, start: u32, rows: u32) -> Range<usize> {
+        let count = self.multisampling.samples();
+        (start * self.width) as usize * count..((start + rows) * self.width) as usize * count
+    }
 
-            for x in pl[0]..=pr[0] {
-                self.put_pixel(x, ys[(x - pl[0]) as usize].floor() as i32, color);
+    /// A canvas like this one, or like this band of one, with nothing drawn
+    /// on it.
+    pub fn blank(&self) -> Self {
+        Self {
+            colors: vec![[0; 3]; self.colors.len()],
+            depth_buffer: vec![0.0; self.depth_buffer.len()],
+            ..*self
+        }
+    }
+
+    /// Calls `draw` on the canvas, but only keeps what it draws on samples
+    /// that aren't black in `mask`, a blank copy of this canvas drawn on.
+    pub fn draw_masked(&mut self, mask: &Self, draw: impl FnOnce(&mut Self)) {
+        let (colors, depth_buffer) = (self.colors.clone(), self.depth_buffer.clone());
+        draw(self);
+        for (sample, color) in mask.colors.iter().enumerate() {
+            if *color == [0; 3] {
+                self.colors[sample] = colors[sample];
+                self.depth_buffer[sample] = depth_buffer[sample];
             }
-        } else {
-            // this line is more vertical than horizontal
-            let (pl, pr) = if p0[1] < p1[1] { (p0, p1) } else { (p1, p0) };
+        }
+    }
 
-            let xs = interpolate(pl[1], pl[0] as f64, pr[1], pr[0] as f64);
+    /// Splits the canvas into up to `count` horizontal bands, each starting
+    /// out with a copy of its rows' color and depth.
+    pub fn split_bands(&self, count: usize) -> Vec<Self> {
+        let count = count.clamp(1, self.height.max(1) as usize) as u32;
+        let band_rows = self.rows.div_ceil(count);
 
-            for y in pl[1]..=pr[1] {
-                self.put_pixel(xs[(y - pl[1]) as usize].floor() as i32, y, color);
+        (0..self.rows)
+            .step_by(band_rows.max(1) as usize)
+            .map(|start| {
+                let rows = band_rows.min(self.rows - start);
+                let samples = self.row_samples(start, rows);
+                Self {
+                    colors: self.colors[samples.clone()].to_vec(),
+                    depth_buffer: self.depth_buffer[samples].to_vec(),
+                    first_row: self.first_row + start,
+                    rows,
+                    ..*self
+                }
+            })
+            .collect()
+    }
+
+    /// Copies a band from `Canvas::split_bands` back into this canvas.
+    pub fn merge_band(&mut self, band: &Self) {
+        let samples = self.row_samples(band.first_row - self.first_row, band.rows);
+        self.colors[samples.clone()].copy_from_slice(&band.colors);
+        self.depth_buffer[samples].copy_from_slice(&band.depth_buffer);
+    }
+
+    /// Calls `draw` on horizontal bands of the canvas from `threads` worker
+    /// threads, then merges the bands back in. Every band has its own depth
+    /// buffer and only keeps the pixels inside it, so as long as `draw` only
+    /// depends on what it's drawing, the result is exactly what drawing on
+    /// the whole canvas at once gives.
+    pub fn draw_in_parallel(&mut self, threads: usize, draw: impl Fn(&mut Self) + Sync) {
+        if threads <= 1 {
+            draw(self);
+            return;
+        }
+
+        let bands: Vec<Mutex<Self>> = self
+            .split_bands(threads * BANDS_PER_THREAD)
+            .into_iter()
+            .map(Mutex::new)
+            .collect();
+        let next_band = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    while let Some(band) = bands.get(next_band.fetch_add(1, Ordering::Relaxed)) {
+                        draw(&mut band.lock().unwrap());
+                    }
+                });
             }
+        });
+
+        for band in bands {
+            self.merge_band(&band.into_inner().unwrap());
         }
     }
 
-    pub fn draw_wireframe_triangle(&mut self, p0: IVec2, p1: IVec2, p2: IVec2, color: DVec3) {
-        self.draw_line(p0, p1, color);
-        self.draw_line(p1, p2, color);
-        self.draw_line(p2, p0, color);
+    // The rows from `top` to `bottom` that this canvas holds. A band of a
+    // bigger canvas only holds some, and a triangle may not touch any of them.
+    fn rows_between(&self, top: i32, bottom: i32) -> RangeInclusive<i32> {
+        let rows = self.y_range();
+        top.max(rows.start)..=bottom.min(rows.end - 1)
     }
 
-    pub fn draw_filled_triangle(
-        &mut self,
-        mut p0: IVec2,
-        mut p1: IVec2,
-        mut p2: IVec2,
-        color: DVec3,
-    ) {
+    /// Draws a line in `style`, blending it into what's already drawn where
+    /// it only partly covers a pixel. The ends are in canvas coordinates, in
+    /// which the pixel `(cx, cy)` covers `cx..cx + 1` and `cy..cy + 1`.
+    pub fn draw_styled_line(&mut self, p0: DVec2, p1: DVec2, style: &LineStyle, color: DVec3) {
+        let (columns, rows) = (self.x_range(), self.y_range());
+        line::trace(p0, p1, style, columns, rows, |cx, cy, _, coverage| {
+            if let Some((x, y)) = self.image_coordinates(cx, cy) {
+                for sample in self.samples(x, y) {
+                    self.blend_sample(sample, color, coverage);
+                }
+            }
+        });
+    }
+
+    /// Like `draw_styled_line`, but with the depth of each end as its `z`,
+    /// and leaving out what's behind anything already drawn. A line
+    /// along the edge of a triangle shows over that triangle. A multisampled
+    /// canvas tests depth at each sample, so a line partly hidden within a
+    /// pixel is partly drawn.
+    pub fn draw_depth_line(&mut self, p0: DVec3, p1: DVec3, style: &LineStyle, color: DVec3) {
+        // depth is linear along the line on the screen
+        let (d0, d1) = (p0.z, p1.z);
+        let (columns, rows) = (self.x_range(), self.y_range());
+        line::trace(
+            p0.truncate(),
+            p1.truncate(),
+            style,
+            columns,
+            rows,
+            |cx, cy, t, coverage| {
+                if let Some((x, y)) = self.image_coordinates(cx, cy) {
+                    let depth = d0 + (d1 - d0) * t;
+                    for sample in self.samples(x, y) {
+                        if self.line_depth_test(sample, depth) {
+                            self.blend_sample(sample, color, coverage);
+                        }
+                    }
+                }
+            },
+        );
+    }
+
+    /// Draws a depth-tested triangle in one color. The `z` of each point
+    /// is its depth, which is linear across the screen.
+    pub fn draw_filled_depth_triangle(&mut self, p0: DVec3, p1: DVec3, p2: DVec3, color: DVec3) {
+        if self.multisampling != Multisampling::Off {
+            self.draw_multisampled_triangle([p0, p1, p2], |_, _, _| color);
+            return;
+        }
+
+        // the pixels the points are in
+        let (mut z0, mut z1, mut z2) = (p0.z, p1.z, p2.z);
+        let [mut p0, mut p1, mut p2] = [p0, p1, p2].map(|p| p.truncate().floor().as_ivec2());
+
         // sort the points by y coordinate
         if p1[1] < p0[1] {
             mem::swap(&mut p1, &mut p0);
+            mem::swap(&mut z1, &mut z0);
         }
         if p2[1] < p0[1] {
             mem::swap(&mut p2, &mut p0);
+            mem::swap(&mut z2, &mut z0)
         }
         if p2[1] < p1[1] {
             mem::swap(&mut p2, &mut p1);
+            mem::swap(&mut z2, &mut z1);
+        }
+
+        let rows = self.rows_between(p0[1], p2[1]);
+        if rows.is_empty() {
+            return;
         }
 
         let (x012, x02) = map_triangle_attribute(
@@ -122,76 +457,293 @@ impl Canvas {
             (p2[1], p2[0] as f64),
         );
 
+        let (depth012, depth02) = map_triangle_attribute((p0[1], z0), (p1[1], z1), (p2[1], z2));
+
         let m = x012.len() / 2;
-        let (x_left, x_right) = if x02[m] < x012[m] {
-            (x02, x012)
+        let (x_left, depth_left, x_right, depth_right) = if x02[m] < x012[m] {
+            (x02, depth02, x012, depth012)
         } else {
-            (x012, x02)
+            (x012, depth012, x02, depth02)
         };
 
-        for y in p0[1]..=p2[1] {
+        for y in rows {
             let i = (y - p0[1]) as usize;
             let x0 = x_left[i] as i32;
             let x1 = x_right[i] as i32;
+            let depth_segment = interpolate(x0, depth_left[i], x1, depth_right[i]);
             for x in x0..=x1 {
-                self.put_pixel(x, y, color);
+                let Some((image_x, image_y)) = self.image_coordinates(x, y) else {
+                    continue;
+                };
+                let sample = self.samples(image_x, image_y).start;
+                if self.depth_test(sample, depth_segment[(x - x0) as usize]) {
+                    self.set_sample(sample, color);
+                }
             }
         }
     }
 
-    pub fn draw_filled_depth_triangle(
+    // Draws a triangle with its corners at `points`, with their depths as
+    // `z`, on a multisampled canvas. Coverage and depth are tested at every
+    // sample, and then `shade` is called once for each pixel where any
+    // sample passed, with the pixel and the barycentric weights of the
+    // corners at the middle of the samples the triangle covers. Its color
+    // goes to each sample that passed.
+    fn draw_multisampled_triangle(
         &mut self,
-        mut p0: IVec2,
-        mut z0: f64,
-        mut p1: IVec2,
-        mut z1: f64,
-        mut p2: IVec2,
-        mut z2: f64,
-        color: DVec3,
+        points: [DVec3; 3],
+        mut shade: impl FnMut(i32, i32, DVec3) -> DVec3,
     ) {
-        // sort the points by y coordinate
-        if p1[1] < p0[1] {
-            mem::swap(&mut p1, &mut p0);
-            mem::swap(&mut z1, &mut z0);
+        let depths = DVec3::new(points[0].z, points[1].z, points[2].z);
+        let points = points.map(|p| p.truncate());
+        let [p0, p1, p2] = points;
+        let area = (p1 - p0).perp_dot(p2 - p0);
+        if area == 0.0 || !area.is_finite() {
+            return;
         }
-        if p2[1] < p0[1] {
-            mem::swap(&mut p2, &mut p0);
-            mem::swap(&mut z2, &mut z0)
+
+        // Each corner's weight is how far a point is over the opposite edge,
+        // as a fraction of the way to the corner.
+        let weights = |point: DVec2| {
+            let [w0, w1, w2] = [1, 2, 0].map(|i| {
+                let (from, to) = (points[i], points[(i + 1) % 3]);
+                (to - from).perp_dot(point - from) / area
+            });
+            DVec3::new(w0, w1, w2)
+        };
+        // A sample exactly on an edge is only inside if that's a top or a
+        // left edge, so that triangles sharing an edge don't both cover it.
+        let owns_edge = [1, 2, 0].map(|i| {
+            let edge = (points[(i + 1) % 3] - points[i]) * area.signum();
+            edge.y < 0.0 || (edge.y == 0.0 && edge.x < 0.0)
+        });
+        let inside = |weights: DVec3| {
+            (0..3).all(|i| weights[i] > 0.0 || (weights[i] == 0.0 && owns_edge[i]))
+        };
+
+        let offsets = self
+            .multisampling
+            .offsets()
+            .iter()
+            .map(|&[x, y]| DVec2::new(x as f64, y as f64) / 16.0 + DVec2::splat(0.5))
+            .collect::<Vec<_>>();
+        let (min, max) = (p0.min(p1).min(p2).floor(), p0.max(p1).max(p2).ceil());
+        let (columns, rows) = (self.x_range(), self.y_range());
+
+        for cy in (min.y as i32).max(rows.start)..(max.y as i32).min(rows.end) {
+            for cx in (min.x as i32).max(columns.start)..(max.x as i32).min(columns.end) {
+                let Some((x, y)) = self.image_coordinates(cx, cy) else {
+                    continue;
+                };
+                let samples = self.samples(x, y);
+                let pixel = DVec2::new(cx as f64, cy as f64);
+
+                // which samples passed, a bit each
+                let mut passed = 0u8;
+                let (mut covered, mut middle) = (0, DVec2::ZERO);
+                for (i, offset) in offsets.iter().enumerate() {
+                    let point = pixel + *offset;
+                    let w = weights(point);
+                    if !inside(w) {
+                        continue;
+                    }
+                    covered += 1;
+                    middle += point;
+                    if self.depth_test(samples.start + i, w.dot(depths)) {
+                        passed |= 1 << i;
+                    }
+                }
+
+                if passed != 0 {
+                    let color = shade(cx, cy, weights(middle / covered as f64));
+                    for (i, sample) in samples.enumerate() {
+                        if passed & (1 << i) != 0 {
+                            self.set_sample(sample, color);
+                        }
+                    }
+                }
+            }
         }
-        if p2[1] < p1[1] {
-            mem::swap(&mut p2, &mut p1);
-            mem::swap(&mut z2, &mut z1);
+    }
+
+    /// Draws a depth-tested triangle whose color varies across it. Each
+    /// point has its depth as its `z` and `1 / w` in `inverse_w`, and `N`
+    /// attributes, which are interpolated perspective-correctly (as
+    /// `attribute / w` alongside `1 / w`). `shade` picks the color of every
+    /// pixel that passes the depth test from its `Fragment`. On a
+    /// multisampled canvas, that's every pixel where any sample does, and the
+    /// fragment is in the middle of the samples the triangle covers.
+    pub fn draw_shaded_depth_triangle<const N: usize>(
+        &mut self,
+        points: [DVec3; 3],
+        inverse_w: [f64; 3],
+        attributes: [[f64; N]; 3],
+        mut shade: impl FnMut(&Fragment<N>) -> DVec3,
+    ) {
+        if self.multisampling != Multisampling::Off {
+            let attributes_over_w: [[f64; 3]; N] =
+                array::from_fn(|k| [0, 1, 2].map(|i| attributes[i][k] * inverse_w[i]));
+            let gradient = |values: [f64; 3]| screen_gradient(points.map(|p| p.truncate()), values);
+            let inverse_w_gradient = gradient(inverse_w);
+            let attribute_gradients = attributes_over_w.map(gradient);
+            let depths = DVec3::new(points[0].z, points[1].z, points[2].z);
+
+            self.draw_multisampled_triangle(points, |x, y, weights| {
+                let inverse_w = weights.dot(DVec3::from(inverse_w));
+                let attributes =
+                    array::from_fn(|k| weights.dot(DVec3::from(attributes_over_w[k])) / inverse_w);
+                shade(&Fragment::new(
+                    x,
+                    y,
+                    weights.dot(depths),
+                    inverse_w,
+                    attributes,
+                    inverse_w_gradient,
+                    attribute_gradients,
+                ))
+            });
+            return;
         }
 
-        let (x012, x02) = map_triangle_attribute(
-            (p0[1], p0[0] as f64),
-            (p1[1], p1[0] as f64),
-            (p2[1], p2[0] as f64),
-        );
+        // the pixels the points are in
+        let pixels = points.map(|p| p.truncate().floor().as_ivec2());
 
-        let (depth012, depth02) =
-            map_triangle_attribute((p0[1], 1.0 / z0), (p1[1], 1.0 / z1), (p2[1], 1.0 / z2));
+        // sort the vertices by y coordinate
+        let mut order = [0, 1, 2];
+        order.sort_by_key(|&i| pixels[i][1]);
+        let [p0, p1, p2] = order.map(|i| pixels[i]);
 
-        let m = x012.len() / 2;
-        let (x_left, depth_left, x_right, depth_right) = if x02[m] < x012[m] {
-            (x02, depth02, x012, depth012)
-        } else {
-            (x012, depth012, x02, depth02)
+        let rows = self.rows_between(p0[1], p2[1]);
+        if rows.is_empty() {
+            return;
+        }
+
+        let depths = order.map(|i| points[i].z);
+        let attributes_over_w: [[f64; 3]; N] =
+            array::from_fn(|k| order.map(|i| attributes[i][k] * inverse_w[i]));
+        let inverse_w = order.map(|i| inverse_w[i]);
+
+        let edges = |values: [f64; 3]| {
+            map_triangle_attribute((p0[1], values[0]), (p1[1], values[1]), (p2[1], values[2]))
         };
+        let (x012, x02) = edges([p0, p1, p2].map(|p| p[0] as f64));
+        let m = x012.len() / 2;
+        let left_is_02 = x02[m] < x012[m];
+        let left_right = |(v012, v02)| if left_is_02 { (v02, v012) } else { (v012, v02) };
+
+        let (x_left, x_right) = left_right((x012, x02));
+        let (depth_left, depth_right) = left_right(edges(depths));
+        let (inverse_w_left, inverse_w_right) = left_right(edges(inverse_w));
+        let attribute_edges: Vec<(Vec<f64>, Vec<f64>)> = attributes_over_w
+            .iter()
+            .map(|values| left_right(edges(*values)))
+            .collect();
 
-        for y in p0[1]..=p2[1] {
+        // `1 / w` and `attribute / w` are linear across the screen, so their
+        // gradients are the same everywhere on the triangle
+        let gradient =
+            |values: [f64; 3]| screen_gradient([p0, p1, p2].map(|p| p.as_dvec2()), values);
+        let inverse_w_gradient = gradient(inverse_w);
+        let attribute_gradients = attributes_over_w.map(gradient);
+
+        for y in rows {
             let i = (y - p0[1]) as usize;
             let x0 = x_left[i] as i32;
             let x1 = x_right[i] as i32;
             let depth_segment = interpolate(x0, depth_left[i], x1, depth_right[i]);
+            let inverse_w_segment = interpolate(x0, inverse_w_left[i], x1, inverse_w_right[i]);
+            let attribute_segments: Vec<Vec<f64>> = attribute_edges
+                .iter()
+                .map(|(left, right)| interpolate(x0, left[i], x1, right[i]))
+                .collect();
             for x in x0..=x1 {
-                let depth = depth_segment[(x - x0) as usize];
-                self.put_depth_pixel(x, y, depth, color);
+                let j = (x - x0) as usize;
+                let depth = depth_segment[j];
+                let Some((image_x, image_y)) = self.image_coordinates(x, y) else {
+                    continue;
+                };
+                let sample = self.samples(image_x, image_y).start;
+                if self.depth_test(sample, depth) {
+                    let inverse_w = inverse_w_segment[j];
+                    let attributes = array::from_fn(|k| attribute_segments[k][j] / inverse_w);
+                    let color = shade(&Fragment::new(
+                        x,
+                        y,
+                        depth,
+                        inverse_w,
+                        attributes,
+                        inverse_w_gradient,
+                        attribute_gradients,
+                    ));
+                    self.set_sample(sample, color);
+                }
             }
         }
     }
 
+    /// The image drawn so far, with each pixel the average of its samples.
+    pub fn image(&self) -> RgbImage {
+        let count = self.multisampling.samples() as u32;
+        ImageBuffer::from_fn(self.width, self.rows, |x, y| {
+            let mut sum = [0u32; 3];
+            for sample in self.samples(x, y) {
+                for (total, c) in sum.iter_mut().zip(self.colors[sample]) {
+                    *total += c as u32;
+                }
+            }
+            image::Rgb(sum.map(|s| ((s + count / 2) / count) as u8))
+        })
+    }
+
     pub fn save(&self, path: impl AsRef<std::path::Path>) -> image::ImageResult<()> {
-        self.image.save(path)
+        self.image().save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The pixel whose lower left corner is at the origin.
+    fn middle_pixel(canvas: &Canvas) -> [u8; 3] {
+        let (x, y) = canvas.image_coordinates(0, 0).unwrap();
+        canvas.image().get_pixel(x, y).0
+    }
+
+    // A big triangle on one side of the line `x = 0.5`, through the middle
+    // of the pixel at the origin.
+    fn half(side: f64, depth: f64) -> [DVec3; 3] {
+        [(0.5, -20.0), (0.5 + 20.0 * side, -20.0), (0.5, 20.0)]
+            .map(|(x, y)| DVec3::new(x, y, depth))
+    }
+
+    #[test]
+    fn half_covered_pixels_resolve_to_half_the_color() {
+        let mut canvas = Canvas::with_multisampling(4, 4, Multisampling::X4);
+        let [p0, p1, p2] = half(-1.0, 0.5);
+        canvas.draw_filled_depth_triangle(p0, p1, p2, DVec3::splat(255.0));
+        assert_eq!(middle_pixel(&canvas), [128; 3]);
+
+        // and the samples left over take whatever covers them
+        let [p0, p1, p2] = half(1.0, 0.25);
+        canvas.draw_filled_depth_triangle(p0, p1, p2, DVec3::new(0.0, 0.0, 255.0));
+        assert_eq!(middle_pixel(&canvas), [128, 128, 255]);
+    }
+
+    #[test]
+    fn samples_are_depth_tested_on_their_own() {
+        let mut canvas = Canvas::with_multisampling(4, 4, Multisampling::X4);
+        let [p0, p1, p2] = half(-1.0, 0.5);
+        canvas.draw_filled_depth_triangle(p0, p1, p2, DVec3::new(255.0, 0.0, 0.0));
+        // behind the first triangle on its half, so only the other half shows
+        let whole =
+            [(-20.0, -20.0), (20.0, -20.0), (0.0, 20.0)].map(|(x, y)| DVec3::new(x, y, 0.25));
+        canvas.draw_filled_depth_triangle(
+            whole[0],
+            whole[1],
+            whole[2],
+            DVec3::new(0.0, 0.0, 255.0),
+        );
+        assert_eq!(middle_pixel(&canvas), [128, 0, 128]);
     }
 }
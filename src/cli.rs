@@ -0,0 +1,158 @@
+use crate::canvas::{Canvas, Multisampling};
+use crate::error::SceneError;
+use crate::raytracer::{self, RenderOptions};
+use crate::render::{RenderMode, Shading};
+use crate::scene::Scene;
+use clap::{Parser, ValueEnum};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Renderer {
+    Raytracer,
+    Rasterizer,
+}
+
+impl Renderer {
+    fn default_scene(&self) -> &'static str {
+        match self {
+            Self::Raytracer => "scene.yaml",
+            Self::Rasterizer => "cube.yaml",
+        }
+    }
+
+    fn default_output(&self) -> &'static str {
+        match self {
+            Self::Raytracer => "test.png",
+            Self::Rasterizer => "raster.png",
+        }
+    }
+}
+
+/// The command line shared by both render binaries.
+#[derive(Debug, Clone, Parser)]
+#[command(version, about = "Renders a YAML scene to an image")]
+pub struct Args {
+    /// The scene file to render [default: scene.yaml for the ray tracer,
+    /// cube.yaml for the rasterizer]
+    pub scene: Option<PathBuf>,
+
+    /// Where to write the image; the format follows the extension
+    /// [default: test.png for the ray tracer, raster.png for the rasterizer]
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Image width in pixels
+    #[arg(long, default_value_t = 512, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+
+    /// Image height in pixels
+    #[arg(long, default_value_t = 512, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: u32,
+
+    /// Which renderer to use [default: depends on the binary]
+    #[arg(short, long, value_enum)]
+    pub renderer: Option<Renderer>,
+
+    /// How many times ray tracer rays may reflect or refract
+    #[arg(long, default_value_t = RenderOptions::default().recursion_depth)]
+    pub recursion_depth: u8,
+
+    /// Ray tracer samples per side of each pixel, overriding the scene's
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+
+    /// Rasterizer shading, overriding the scene's
+    #[arg(long, value_enum)]
+    pub shading: Option<Shading>,
+
+    /// Rasterizer render mode, overriding the scene's
+    #[arg(long, value_enum)]
+    pub render_mode: Option<RenderMode>,
+
+    /// Rasterizer multisample anti-aliasing; the ray tracer has `--samples`
+    #[arg(long, value_enum, default_value_t = Multisampling::Off)]
+    pub msaa: Multisampling,
+
+    /// Worker threads to render with [default: one per CPU]
+    #[arg(short = 'j', long)]
+    pub threads: Option<NonZeroUsize>,
+}
+
+fn describe(error: SceneError, path: &Path) -> String {
+    match error.file() {
+        Some(_) => error.to_string(),
+        None => format!("{}: {}", path.display(), error),
+    }
+}
+
+fn render(args: &Args, renderer: Renderer) -> Result<(), String> {
+    let scene_path = args
+        .scene
+        .clone()
+        .unwrap_or_else(|| renderer.default_scene().into());
+    let output_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| renderer.default_output().into());
+    let threads = args
+        .threads
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, usize::from);
+
+    let multisampling = match renderer {
+        Renderer::Raytracer => Multisampling::Off,
+        Renderer::Rasterizer => args.msaa,
+    };
+    let mut canvas = Canvas::with_multisampling(args.width, args.height, multisampling);
+
+    match renderer {
+        Renderer::Raytracer => {
+            let scene =
+                raytracer::Scene::load(&scene_path).map_err(|e| describe(e, &scene_path))?;
+            let options = RenderOptions {
+                recursion_depth: args.recursion_depth,
+                threads,
+                samples: args.samples,
+            };
+            raytracer::render(&scene, &mut canvas, options);
+        }
+        Renderer::Rasterizer => {
+            let mut scene = Scene::load(&scene_path).map_err(|e| describe(e, &scene_path))?;
+            if let Some(shading) = args.shading {
+                scene.shading = shading;
+            }
+            if let Some(render_mode) = args.render_mode {
+                scene.render_mode = render_mode;
+            }
+            canvas.fill(scene.background);
+            scene
+                .transform()
+                .section()
+                .cast_shadows()
+                .cull_back_faces()
+                .clip()
+                .render_in_parallel(&mut canvas, threads);
+        }
+    }
+
+    canvas
+        .save(&output_path)
+        .map_err(|e| format!("couldn't write {}: {}", output_path.display(), e))
+}
+
+/// Parses the command line and renders with `default_renderer` unless told
+/// otherwise, reporting any failure on stderr.
+pub fn main(default_renderer: Renderer) -> ExitCode {
+    let args = Args::parse();
+    let renderer = args.renderer.unwrap_or(default_renderer);
+
+    match render(&args, renderer) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,76 +1,98 @@
-use crate::scene::{Camera, Object, Scene, Triangle};
-use glam::DVec3;
+use crate::scene::{Camera, Object, Scene, Triangle, Vertex};
+use glam::{DVec3, DVec4};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Plane {
-    normal: DVec3,
-    d: f64,
+    pub normal: DVec3,
+    pub d: f64,
 }
 
 impl Plane {
-    fn signed_distance(&self, vertex: DVec3) -> f64 {
-        self.normal.dot(vertex) + self.d
+    /// The plane of points `p` where `normal.dot(p) + d` is zero. Clipping
+    /// keeps what's on the side `normal` points to.
+    pub fn new(normal: DVec3, d: f64) -> Self {
+        Self { normal, d }
     }
 
-    fn intersection(&self, v0: DVec3, v1: DVec3) -> DVec3 {
-        let t = (-self.d - self.normal.dot(v0)) / self.normal.dot(v1 - v0);
-        v0 + t * (v1 - v0)
+    pub fn signed_distance(&self, vertex: DVec3) -> f64 {
+        self.normal.dot(vertex) + self.d
     }
 }
 
+// Where the edge between two vertices crosses zero distance, with each vertex
+// alongside its distance.
+fn intersection((v0, d0): (Vertex, f64), (v1, d1): (Vertex, f64)) -> Vertex {
+    v0.lerp(&v1, d0 / (d0 - d1))
+}
+
 impl Triangle {
-    // We call this with the one positive vertex as `a`
-    fn clip_one_positive(&self, a: DVec3, b: DVec3, c: DVec3, plane: Plane) -> Vec<Self> {
+    // We call this with the one positive vertex as `a`, and `ab`, `bc` and
+    // `ca` saying which of the edges between them to outline
+    fn clip_one_positive(
+        &self,
+        [a, b, c]: [(Vertex, f64); 3],
+        [ab, _, ca]: [bool; 3],
+    ) -> Vec<Self> {
+        let vertices = [a.0, intersection(a, b), intersection(a, c)];
         vec![Self {
-            v0: a,
-            v1: plane.intersection(a, b),
-            v2: plane.intersection(a, c),
-            color: self.color,
+            edges: [ab, false, ca],
+            ..self.with_vertices(vertices)
         }]
     }
 
     // We call this with the one negative vertex as `c`
-    fn clip_one_negative(&self, a: DVec3, b: DVec3, c: DVec3, plane: Plane) -> Vec<Self> {
-        let ap = plane.intersection(a, c);
-        let bp = plane.intersection(b, c);
+    fn clip_one_negative(
+        &self,
+        [a, b, c]: [(Vertex, f64); 3],
+        [ab, bc, ca]: [bool; 3],
+    ) -> Vec<Self> {
+        let (ap, bp) = (intersection(a, c), intersection(b, c));
+        let (a, b) = (a.0, b.0);
 
         vec![
             Self {
-                v0: a,
-                v1: b,
-                v2: ap,
-                color: self.color,
+                edges: [ab, false, ca],
+                ..self.with_vertices([a, b, ap])
             },
             Self {
-                v0: ap,
-                v1: b,
-                v2: bp,
-                color: self.color,
+                edges: [false, bc, false],
+                ..self.with_vertices([ap, b, bp])
             },
         ]
     }
 
-    fn clip_against_plane(&self, plane: Plane) -> Vec<Self> {
-        let d0 = plane.signed_distance(self.v0);
-        let d1 = plane.signed_distance(self.v1);
-        let d2 = plane.signed_distance(self.v2);
-
-        match (d0.signum() as i32, d1.signum() as i32, d2.signum() as i32) {
-            (1, 1, 1) => vec![*self],
-            (-1, -1, -1) => vec![],
-            (1, -1, -1) => self.clip_one_positive(self.v0, self.v1, self.v2, plane),
-            (-1, 1, -1) => self.clip_one_positive(self.v1, self.v0, self.v2, plane),
-            (-1, -1, 1) => self.clip_one_positive(self.v2, self.v0, self.v1, plane),
-            (-1, 1, 1) => self.clip_one_negative(self.v1, self.v2, self.v0, plane),
-            (1, -1, 1) => self.clip_one_negative(self.v0, self.v2, self.v1, plane),
-            (1, 1, -1) => self.clip_one_negative(self.v0, self.v1, self.v2, plane),
+    /// Keeps the pieces of the triangle where `distance` is positive. It has
+    /// to change linearly along each edge for the cuts to land on zero.
+    fn clip_by(&self, distance: impl Fn(DVec3) -> f64) -> Vec<Self> {
+        let vertices = self.vertices().map(|v| (v, distance(v.position)));
+        let signs = vertices.map(|(_, d)| d.signum() as i32);
+        // The vertices starting from the `i`th, in the same order around the
+        // triangle, so that the pieces keep its winding.
+        let from = |i: usize| [0, 1, 2].map(|k| vertices[(i + k) % 3]);
+        let edges_from = |i: usize| [0, 1, 2].map(|k| self.edges[(i + k) % 3]);
+
+        match signs {
+            [1, 1, 1] => vec![*self],
+            [-1, -1, -1] => vec![],
+            [1, -1, -1] => self.clip_one_positive(from(0), edges_from(0)),
+            [-1, 1, -1] => self.clip_one_positive(from(1), edges_from(1)),
+            [-1, -1, 1] => self.clip_one_positive(from(2), edges_from(2)),
+            [-1, 1, 1] => self.clip_one_negative(from(1), edges_from(1)),
+            [1, -1, 1] => self.clip_one_negative(from(2), edges_from(2)),
+            [1, 1, -1] => self.clip_one_negative(from(0), edges_from(0)),
             _ => unreachable!(),
         }
     }
+
+    pub fn clip_against_plane(&self, plane: Plane) -> Vec<Self> {
+        self.clip_by(|p| plane.signed_distance(p))
+    }
 }
 
 impl Object {
-    fn clip_against_plane(&self, plane: Plane) -> Option<Self> {
+    // Clips by `distance` as `Triangle::clip_by` does, where `plane` is its
+    // zero, for checking the bounding sphere against first.
+    fn clip_by(&self, plane: Plane, distance: impl Fn(DVec3) -> f64 + Copy) -> Option<Self> {
         let d = plane.signed_distance(self.bounding_center);
 
         if d > self.bounding_radius {
@@ -83,18 +105,33 @@ impl Object {
             triangles: self
                 .triangles
                 .iter()
-                .flat_map(|t| t.clip_against_plane(plane))
+                .flat_map(|t| t.clip_by(distance))
                 .collect(),
             transform: self.transform,
             bounding_center: self.bounding_center,
             bounding_radius: self.bounding_radius,
+            sections: self.sections.clone(),
         })
     }
 
-    fn clip(&self, planes: &[Plane]) -> Option<Self> {
+    /// What's left of the object on the kept side of every plane, or `None`
+    /// if that's nothing.
+    pub fn clip(&self, planes: &[Plane]) -> Option<Self> {
         let mut object = self.clone();
         for plane in planes {
-            object = self.clip_against_plane(*plane)?;
+            object = object.clip_by(*plane, |p| plane.signed_distance(p))?;
+        }
+        Some(object)
+    }
+
+    /// What's left of the object within what `camera` sees. Each vertex is
+    /// projected into clip space and measured against the `-w..=w` bounds
+    /// there, so the clipping happens in homogeneous coordinates.
+    pub fn clip_to_view(&self, camera: &Camera) -> Option<Self> {
+        let projection = camera.projection();
+        let mut object = self.clone();
+        for (bound, plane) in camera.clip_bounds() {
+            object = object.clip_by(plane, |p| bound.dot(projection * p.extend(1.0)))?;
         }
         Some(object)
     }
@@ -102,41 +139,279 @@ impl Object {
 
 impl Scene {
     pub fn clip(&self) -> Self {
-        let planes = self.camera.clipping_planes();
-        Self {
-            objects: self
-                .objects
+        self.with_objects(
+            self.objects
                 .iter()
-                .filter_map(|obj| obj.clip(&planes))
+                .filter_map(|obj| obj.clip_to_view(&self.camera))
                 .collect(),
-            camera: self.camera,
-        }
+        )
     }
 }
 
 impl Camera {
+    // The `-w..=w` bounds of clip space, near, sides and far, each as the
+    // vector whose dot product with a point in clip space is how far inside
+    // the bound the point is, and with the plane it is in camera space.
+    fn clip_bounds(&self) -> Vec<(DVec4, Plane)> {
+        let projection = self.projection();
+        let (x, y, z, w) = (DVec4::X, DVec4::Y, DVec4::Z, DVec4::W);
+        [w + z, w + x, w - x, w + y, w - y, w - z]
+            .into_iter()
+            .filter_map(|bound| {
+                let plane = projection.transpose() * bound;
+                // a far plane at infinity has nothing left of its normal
+                let length = plane.truncate().length();
+                let plane = Plane::new(plane.truncate() / length, plane.w / length);
+                (length > 0.0).then_some((bound, plane))
+            })
+            .collect()
+    }
+
+    /// The planes around what the camera sees, in camera space: the near
+    /// plane, one through each side of the viewport, and the far plane if
+    /// there is one. Each is where one of the `-w..=w` bounds of clip space
+    /// is, brought back through the projection.
     pub fn clipping_planes(&self) -> Vec<Plane> {
-        vec![
-            Plane {
-                normal: DVec3::new(0.0, 0.0, 1.0),
-                d: -self.viewport.distance,
-            },
-            Plane {
-                normal: DVec3::new(1.0, 0.0, 1.0).normalize(),
-                d: 0.0,
-            },
-            Plane {
-                normal: DVec3::new(-1.0, 0.0, 1.0).normalize(),
-                d: 0.0,
-            },
-            Plane {
-                normal: DVec3::new(0.0, 1.0, 1.0).normalize(),
-                d: 0.0,
-            },
-            Plane {
-                normal: DVec3::new(0.0, -1.0, 1.0).normalize(),
-                d: 0.0,
+        self.clip_bounds()
+            .into_iter()
+            .map(|(_, plane)| plane)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Projection, Viewport};
+    use crate::material::Material;
+    use crate::section::Sections;
+    use glam::{DMat4, DVec2};
+
+    const EPSILON: f64 = 1e-9;
+
+    // A wide viewport, off to the right and down, with a far plane.
+    fn viewport() -> Viewport {
+        serde_yaml::from_str("{width: 2, height: 1, distance: 0.5, offset: [0.5, -0.25], far: 8}")
+            .unwrap()
+    }
+
+    fn camera_with(viewport: Viewport, projection: Projection) -> Camera {
+        Camera {
+            transform: DMat4::IDENTITY,
+            viewport,
+            projection: projection.matrix(&viewport),
+            eye: projection.eye(),
+        }
+    }
+
+    fn camera() -> Camera {
+        camera_with(viewport(), Projection::Perspective)
+    }
+
+    fn triangle([v0, v1, v2]: [DVec3; 3]) -> Triangle {
+        Triangle {
+            v0,
+            v1,
+            v2,
+            normals: [DVec3::Z; 3],
+            uvs: [DVec2::ZERO; 3],
+            colors: [DVec3::ONE; 3],
+            edges: [true; 3],
+            material: Material::new(DVec3::ONE),
+            center: (v0 + v1 + v2) / 3.0,
+        }
+    }
+
+    fn area(t: &Triangle) -> f64 {
+        (t.v1 - t.v0).cross(t.v2 - t.v0).length() / 2.0
+    }
+
+    fn clip(triangle: &Triangle, planes: &[Plane]) -> Vec<Triangle> {
+        planes.iter().fold(vec![*triangle], |pieces, plane| {
+            pieces
+                .iter()
+                .flat_map(|t| t.clip_against_plane(*plane))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn planes_meet_at_the_viewport_corners() {
+        let camera = camera();
+        let planes = camera.clipping_planes();
+        assert_eq!(planes.len(), 6);
+
+        let [min, max] = camera.viewport.corners();
+        assert_eq!(min, DVec2::new(-0.5, -0.75));
+        assert_eq!(max, DVec2::new(1.5, 0.25));
+        for corner in [min, max, DVec2::new(min.x, max.y), DVec2::new(max.x, min.y)] {
+            // each corner is on the near plane and a side plane either way
+            let point = corner.extend(0.5);
+            let on = planes
+                .iter()
+                .filter(|p| p.signed_distance(point).abs() < EPSILON)
+                .count();
+            assert_eq!(on, 3, "{:?}", corner);
+            // and the corners further out are on the far plane
+            let far = corner.extend(0.5) * 16.0;
+            assert!(planes[5].signed_distance(far).abs() < EPSILON);
+            assert!(planes.iter().all(|p| p.signed_distance(far) > -EPSILON));
+        }
+    }
+
+    #[test]
+    fn projection_maps_the_frustum_to_clip_space_bounds() {
+        let camera = camera();
+        let [min, max] = camera.viewport.corners();
+        for (corner, ndc) in [
+            (min, -DVec2::ONE),
+            (max, DVec2::ONE),
+            (DVec2::new(min.x, max.y), DVec2::new(-1.0, 1.0)),
+        ] {
+            for (scale, z) in [(1.0, -1.0), (16.0, 1.0)] {
+                let clip = camera.projection() * (corner.extend(0.5) * scale).extend(1.0);
+                let expected = ndc.extend(z);
+                assert!((clip.truncate() / clip.w - expected).abs().max_element() < EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_side_planes_follow_the_rays() {
+        for projection in [
+            Projection::Orthographic,
+            Projection::Cabinet,
+            Projection::Oblique {
+                angle: 30.0,
+                depth: 2.0,
             },
-        ]
+        ] {
+            let camera = camera_with(viewport(), projection);
+            let planes = camera.clipping_planes();
+            assert_eq!(planes.len(), 6);
+
+            let [min, max] = camera.viewport.corners();
+            for corner in [min, max, DVec2::new(min.x, max.y), DVec2::new(max.x, min.y)] {
+                // from the near plane to the far one along the ray through
+                // each corner of the viewport
+                for t in [0.0, 1.0, 7.5] {
+                    let point = corner.extend(0.5) - camera.toward_eye(DVec3::ZERO) * t;
+                    let on = planes[1..5]
+                        .iter()
+                        .filter(|p| p.signed_distance(point).abs() < EPSILON)
+                        .count();
+                    assert_eq!(on, 2, "{:?} {:?} {}", projection, corner, t);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn far_plane_is_optional() {
+        let mut viewport = viewport();
+        viewport.far = None;
+        let camera = camera_with(viewport, Projection::Perspective);
+        let planes = camera.clipping_planes();
+        assert_eq!(planes.len(), 5);
+
+        let distant = triangle([
+            DVec3::new(0.0, 0.0, 1e6),
+            DVec3::new(1.0, 0.0, 1e6),
+            DVec3::new(0.0, -1.0, 1e6),
+        ]);
+        assert_eq!(clip(&distant, &planes).len(), 1);
+    }
+
+    #[test]
+    fn clips_triangles_straddling_every_plane() {
+        let camera = camera();
+        let planes = camera.clipping_planes();
+        // well inside the frustum, on the line through the viewport's middle
+        let axis = DVec3::new(0.5, -0.25, 0.5);
+        let middle = axis * 8.0;
+        assert!(planes.iter().all(|p| p.signed_distance(middle) > 0.5));
+        // a way out through each plane from the middle, and no other
+        let ways_out = [-axis, -DVec3::X, DVec3::X, -DVec3::Y, DVec3::Y, axis];
+
+        for (i, (plane, way_out)) in planes.iter().zip(ways_out).enumerate() {
+            let t = plane.signed_distance(middle) / -plane.normal.dot(way_out);
+            let across = middle + way_out * t * 1.05;
+            let (u, v) = plane.normal.any_orthonormal_pair();
+            let (u, v) = (u * 0.1, v * 0.1);
+
+            let one_outside = triangle([across, middle + u, middle + v]);
+            let two_outside = triangle([across + u, across + v, middle]);
+            for (original, outside) in [(one_outside, 1), (two_outside, 2)] {
+                let pieces = clip(&original, &planes);
+                assert_eq!(pieces.len(), 3 - outside, "plane {}", i);
+
+                let clipped_area: f64 = pieces.iter().map(area).sum();
+                assert!(clipped_area > 0.0 && clipped_area < area(&original));
+                for piece in &pieces {
+                    let vertices = [piece.v0, piece.v1, piece.v2];
+                    for v in vertices {
+                        assert!(planes.iter().all(|p| p.signed_distance(v) > -EPSILON));
+                    }
+                    // the cut runs along the plane, with the same winding
+                    assert!(vertices
+                        .iter()
+                        .any(|v| plane.signed_distance(*v).abs() < EPSILON));
+                    assert!(piece.face_normal().dot(original.face_normal()) > 1.0 - EPSILON);
+                }
+                // What's left of the triangle's own edges is outlined, but
+                // not the cut or the seam between the pieces.
+                let outlined = pieces.iter().flat_map(|t| t.edges).filter(|&e| e).count();
+                assert_eq!(outlined, 4 - outside, "plane {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_entirely_outside_are_dropped() {
+        let camera = camera();
+        let planes = camera.clipping_planes();
+        let behind = triangle([
+            DVec3::new(0.0, 0.0, -1.0),
+            DVec3::new(1.0, 0.0, -1.0),
+            DVec3::new(0.0, 1.0, -1.0),
+        ]);
+        // inside the symmetric frustum, but not this one
+        let left = triangle([
+            DVec3::new(-1.5, 0.0, 1.0),
+            DVec3::new(-1.2, 0.0, 1.0),
+            DVec3::new(-1.5, 0.1, 1.0),
+        ]);
+        assert!(clip(&behind, &planes).is_empty());
+        assert!(clip(&left, &planes).is_empty());
+    }
+
+    #[test]
+    fn objects_are_clipped_against_every_plane() {
+        let camera = camera();
+        let planes = camera.clipping_planes();
+        // straddles the left, right, top and bottom planes all at once
+        let big = triangle([
+            DVec3::new(-20.0, -20.0, 4.0),
+            DVec3::new(20.0, -20.0, 4.0),
+            DVec3::new(0.0, 20.0, 4.0),
+        ]);
+        let object = Object {
+            triangles: vec![big],
+            transform: DMat4::IDENTITY,
+            bounding_center: DVec3::new(0.0, 0.0, 4.0),
+            bounding_radius: 30.0,
+            sections: Sections::default(),
+        };
+
+        let clipped = object.clip_to_view(&camera).unwrap();
+        assert!(!clipped.triangles.is_empty());
+        for t in &clipped.triangles {
+            for v in [t.v0, t.v1, t.v2] {
+                assert!(planes.iter().all(|p| p.signed_distance(v) > -EPSILON));
+                // and within `-w..=w` once projected
+                let clip = camera.projection() * v.extend(1.0);
+                assert!(clip.truncate().abs().max_element() <= clip.w + EPSILON);
+            }
+        }
     }
 }
@@ -1,34 +1,50 @@
-use crate::scene::{Object, Scene, Triangle};
+use crate::render::RenderMode;
+use crate::scene::{Camera, Object, Scene, Triangle};
+use glam::DVec3;
+
+impl Camera {
+    /// The way back along the ray that `point`, in camera space, is seen
+    /// by: toward the camera, or straight back along the parallel rays.
+    pub fn toward_eye(&self, point: DVec3) -> DVec3 {
+        self.eye.truncate() - point * self.eye.w
+    }
+}
 
 impl Triangle {
-    fn is_front_facing(&self) -> bool {
+    pub fn is_front_facing(&self, camera: &Camera) -> bool {
         let normal = (self.v1 - self.v0).cross(self.v2 - self.v0);
 
-        normal.dot(self.v0) <= 0.0
+        normal.dot(camera.toward_eye(self.v0)) >= 0.0
     }
 }
 
 impl Object {
-    fn cull_back_faces(&self) -> Self {
+    // A capped object keeps its back faces, which is where its cut surfaces
+    // show through, and a wireframe shows every edge, back faces' too.
+    fn cull_back_faces(&self, scene: &Scene) -> Self {
+        let keep_all = self.sections.cap().is_some() || scene.render_mode == RenderMode::Wireframe;
         Self {
             triangles: self
                 .triangles
                 .iter()
-                .filter(|t| t.is_front_facing())
+                .filter(|t| keep_all || t.is_front_facing(&scene.camera))
                 .copied()
                 .collect(),
             transform: self.transform,
             bounding_center: self.bounding_center,
             bounding_radius: self.bounding_radius,
+            sections: self.sections.clone(),
         }
     }
 }
 
 impl Scene {
     pub fn cull_back_faces(&self) -> Self {
-        Self {
-            objects: self.objects.iter().map(Object::cull_back_faces).collect(),
-            camera: self.camera,
-        }
+        self.with_objects(
+            self.objects
+                .iter()
+                .map(|obj| obj.cull_back_faces(self))
+                .collect(),
+        )
     }
 }
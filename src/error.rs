@@ -0,0 +1,292 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong loading a scene. Problems with the scene's
+/// contents come with the YAML path of the offending item, like
+/// `models.cube.triangles[3].vertices[1]`.
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        file: PathBuf,
+        source: io::Error,
+    },
+    /// The file isn't valid YAML or doesn't match the scene format. The
+    /// message from `serde_yaml` already includes the path and location.
+    Yaml {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+    UnknownModel {
+        path: String,
+        model: String,
+    },
+    /// An index into one of a model's lists, such as its `vertices`, that
+    /// is past the end of it.
+    IndexOutOfRange {
+        path: String,
+        index: usize,
+        len: usize,
+        list: &'static str,
+    },
+    EmptyModel {
+        path: String,
+    },
+    VertexColorCount {
+        path: String,
+        colors: usize,
+        vertices: usize,
+    },
+    UnknownTexture {
+        path: String,
+        texture: String,
+    },
+    Image {
+        path: String,
+        file: PathBuf,
+        source: image::ImageError,
+    },
+    /// A rasterizer camera with a parallel projection but no far plane, which
+    /// it needs for its depth range.
+    NoFarPlane {
+        path: String,
+    },
+    /// A camera with both a `position` and a `transform.translation`, which
+    /// say the same thing.
+    CameraPositionAndTranslation {
+        path: String,
+    },
+}
+
+impl SceneError {
+    /// The scene file the error is about, if it's a problem with the file
+    /// itself rather than with what the scene describes.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Self::Io { file, .. } | Self::Yaml { file, .. } => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Nests the path of the offending item under `parent`.
+    pub fn within(self, parent: &str) -> Self {
+        let nest = |path: String| format!("{}.{}", parent, path);
+        match self {
+            Self::UnknownModel { path, model } => Self::UnknownModel {
+                path: nest(path),
+                model,
+            },
+            Self::IndexOutOfRange {
+                path,
+                index,
+                len,
+                list,
+            } => Self::IndexOutOfRange {
+                path: nest(path),
+                index,
+                len,
+                list,
+            },
+            Self::EmptyModel { path } => Self::EmptyModel { path: nest(path) },
+            Self::VertexColorCount {
+                path,
+                colors,
+                vertices,
+            } => Self::VertexColorCount {
+                path: nest(path),
+                colors,
+                vertices,
+            },
+            Self::UnknownTexture { path, texture } => Self::UnknownTexture {
+                path: nest(path),
+                texture,
+            },
+            Self::Image { path, file, source } => Self::Image {
+                path: nest(path),
+                file,
+                source,
+            },
+            Self::NoFarPlane { path } => Self::NoFarPlane { path: nest(path) },
+            Self::CameraPositionAndTranslation { path } => {
+                Self::CameraPositionAndTranslation { path: nest(path) }
+            }
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { file, source } => write!(f, "couldn't read {}: {}", file.display(), source),
+            Self::Yaml { file, source } => {
+                write!(f, "couldn't parse {}: {}", file.display(), source)
+            }
+            Self::UnknownModel { path, model } => {
+                write!(f, "{}: there is no model named `{}`", path, model)
+            }
+            Self::IndexOutOfRange {
+                path,
+                index,
+                len,
+                list,
+            } => write!(
+                f,
+                "{}: index {} is out of range for the model's {} {}",
+                path, index, len, list
+            ),
+            Self::EmptyModel { path } => write!(f, "{}: the model has no vertices", path),
+            Self::VertexColorCount {
+                path,
+                colors,
+                vertices,
+            } => write!(
+                f,
+                "{}: the model has {} colors for {} vertices",
+                path, colors, vertices
+            ),
+            Self::UnknownTexture { path, texture } => {
+                write!(f, "{}: there is no texture named `{}`", path, texture)
+            }
+            Self::Image { path, file, source } => {
+                write!(f, "{}: couldn't load {}: {}", path, file.display(), source)
+            }
+            Self::NoFarPlane { path } => {
+                write!(f, "{}: parallel projections need a `far` plane", path)
+            }
+            Self::CameraPositionAndTranslation { path } => write!(
+                f,
+                "{}: a camera can't have both a `position` and a `transform.translation`",
+                path
+            ),
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Yaml { source, .. } => Some(source),
+            Self::Image { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Scene;
+    use crate::scene_definition::SceneDefinition;
+    use std::fs;
+
+    // A scene with one triangle `model` and an instance of it, with whatever
+    // else `extra` adds on.
+    fn scene(model: &str, extra: &str) -> String {
+        format!(
+            "models:\n  triangle: {}\ninstances:\n  - {{model: triangle, transform: {{}}}}\ncamera: {{}}\n{}",
+            model, extra
+        )
+    }
+
+    const MODEL: &str =
+        "{vertices: [[0, 0, 1], [1, 0, 1], [0, 1, 1]], triangles: [{vertices: [0, 1, 2]}]}";
+
+    fn error(yaml: &str) -> SceneError {
+        let definition: SceneDefinition = serde_yaml::from_str(yaml).unwrap();
+        Scene::try_from(definition).unwrap_err()
+    }
+
+    fn assert_path(error: SceneError, path: &str) {
+        let message = error.to_string();
+        assert!(message.starts_with(&format!("{}: ", path)), "{}", message);
+    }
+
+    #[test]
+    fn a_good_scene_loads() {
+        let definition: SceneDefinition = serde_yaml::from_str(&scene(MODEL, "")).unwrap();
+        assert!(Scene::try_from(definition).is_ok());
+    }
+
+    #[test]
+    fn unknown_models_point_at_the_instance() {
+        let yaml = scene(MODEL, "").replace("model: triangle", "model: square");
+        let e = error(&yaml);
+        assert!(matches!(&e, SceneError::UnknownModel { model, .. } if model == "square"));
+        assert_path(e, "instances[0].model");
+    }
+
+    #[test]
+    fn out_of_range_indices_point_at_the_index() {
+        let model = "{vertices: [[0, 0, 1], [1, 0, 1], [0, 1, 1]], triangles: [{vertices: [0, 1, 2]}, {vertices: [0, 3, 2]}]}";
+        let e = error(&scene(model, ""));
+        assert!(matches!(
+            e,
+            SceneError::IndexOutOfRange {
+                index: 3,
+                len: 3,
+                ..
+            }
+        ));
+        assert_path(e, "models.triangle.triangles[1].vertices[1]");
+    }
+
+    #[test]
+    fn model_problems_point_at_the_model() {
+        assert_path(
+            error(&scene("{vertices: [], triangles: []}", "")),
+            "models.triangle.vertices",
+        );
+        let colored = MODEL.replace("triangles", "colors: [[255, 0, 0]], triangles");
+        assert_path(error(&scene(&colored, "")), "models.triangle.colors");
+        let textured = MODEL.replace("triangles", "texture: checker, triangles");
+        assert_path(error(&scene(&textured, "")), "models.triangle.texture");
+    }
+
+    #[test]
+    fn missing_images_point_at_the_texture() {
+        let e = error(&scene(
+            MODEL,
+            "textures:\n  checker: {path: /nonexistent/checker.png}\n",
+        ));
+        assert!(matches!(&e, SceneError::Image { file, .. } if file.ends_with("checker.png")));
+        assert!(e.source().is_some());
+        assert_path(e, "textures.checker.path");
+    }
+
+    #[test]
+    fn camera_problems_point_at_the_camera() {
+        let parallel = scene(MODEL, "").replace("camera: {}", "camera: {projection: Orthographic}");
+        assert_path(error(&parallel), "camera.viewport");
+        let both = scene(MODEL, "").replace(
+            "camera: {}",
+            "camera: {position: [0, 0, 0], transform: {translation: [0, 0, 1]}}",
+        );
+        assert_path(error(&both), "camera.position");
+    }
+
+    #[test]
+    fn file_problems_name_the_file() {
+        let missing = Path::new("/nonexistent/scene.yaml");
+        let e = Scene::load(missing).unwrap_err();
+        assert!(matches!(e, SceneError::Io { .. }));
+        assert_eq!(e.file(), Some(missing));
+
+        let invalid = std::env::temp_dir().join("render-3d-invalid-scene.yaml");
+        fs::write(&invalid, "models: [").unwrap();
+        let e = Scene::load(&invalid).unwrap_err();
+        fs::remove_file(&invalid).unwrap();
+        assert!(matches!(e, SceneError::Yaml { .. }));
+        assert_eq!(e.file(), Some(invalid.as_path()));
+    }
+
+    #[test]
+    fn within_nests_paths() {
+        let e = SceneError::EmptyModel {
+            path: "vertices".to_string(),
+        };
+        assert_path(e.within("models.cube"), "models.cube.vertices");
+    }
+}
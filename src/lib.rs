@@ -1,10 +1,20 @@
+pub mod bvh;
 pub mod camera;
 pub mod canvas;
+pub mod cli;
 pub mod clip;
 pub mod cull;
+pub mod error;
 pub mod light;
+pub mod line;
+pub mod material;
+pub mod raytracer;
 pub mod render;
+pub mod sampling;
 pub mod scene;
 pub mod scene_definition;
+pub mod section;
+pub mod shadow;
+pub mod texture;
 pub mod transform;
 pub mod utils;
@@ -1,21 +1,176 @@
+use crate::sampling::{stratified, Rng};
 use glam::DVec3;
 use serde::Deserialize;
+use std::f64::consts::PI;
 
-fn reflect_ray(ray: DVec3, normal: DVec3) -> DVec3 {
+pub fn reflect_ray(ray: DVec3, normal: DVec3) -> DVec3 {
     2.0 * normal.dot(ray) * normal - ray
 }
 
+/// The shape of an area light, centered on its `position`.
 #[derive(Debug, Clone, Copy, Deserialize)]
 #[serde(tag = "type")]
-enum Light {
-    Ambient { intensity: f64 },
-    Directional { intensity: f64, direction: DVec3 },
-    Point { intensity: f64, position: DVec3 },
+pub enum AreaShape {
+    /// A parallelogram spanned by the edges `u` and `v`.
+    Rectangle {
+        u: DVec3,
+        v: DVec3,
+    },
+    Sphere {
+        radius: f64,
+    },
+}
+
+fn default_area_samples() -> u32 {
+    4
+}
+
+fn default_falloff() -> f64 {
+    1.0
+}
+
+fn default_color() -> DVec3 {
+    DVec3::splat(255.0)
+}
+
+/// Every light has an `intensity` and an optional RGB `color`, from 0 to
+/// 255 per channel like surface colors, which defaults to white.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "type")]
+pub enum Light {
+    Ambient {
+        intensity: f64,
+        #[serde(default = "default_color")]
+        color: DVec3,
+    },
+    Directional {
+        intensity: f64,
+        #[serde(default = "default_color")]
+        color: DVec3,
+        direction: DVec3,
+    },
+    Point {
+        intensity: f64,
+        #[serde(default = "default_color")]
+        color: DVec3,
+        position: DVec3,
+    },
+    /// A point light that only shines in a cone around `direction`. It's at
+    /// full strength within `inner_angle` of the axis and fades out to
+    /// nothing at `outer_angle`, both in degrees, with the fade shaped by
+    /// the `falloff` exponent.
+    Spot {
+        intensity: f64,
+        #[serde(default = "default_color")]
+        color: DVec3,
+        position: DVec3,
+        direction: DVec3,
+        inner_angle: f64,
+        outer_angle: f64,
+        #[serde(default = "default_falloff")]
+        falloff: f64,
+    },
+    /// A light with a size, which casts soft shadows. It's sampled as
+    /// `samples`×`samples` point lights spread over its shape.
+    Area {
+        intensity: f64,
+        #[serde(default = "default_color")]
+        color: DVec3,
+        position: DVec3,
+        shape: AreaShape,
+        #[serde(default = "default_area_samples")]
+        samples: u32,
+        #[serde(default)]
+        seed: u64,
+    },
 }
 
 impl Light {
-    fn compute_lighting(&self, point: DVec3, normal: DVec3, view: DVec3, specular: i32) -> f64 {
-        if let Self::Ambient { intensity } = self {
+    /// Splits the light into the lights that `point` should be shaded and
+    /// shadowed with. Area lights become a stratified set of point lights,
+    /// sharing out their intensity, whose positions are jittered differently
+    /// for every `point`. Any other light is left as it is.
+    pub fn sample(&self, point: DVec3) -> Vec<Self> {
+        let (intensity, color, position, shape, samples, seed) = match *self {
+            Self::Area {
+                intensity,
+                color,
+                position,
+                shape,
+                samples,
+                seed,
+            } => (intensity, color, position, shape, samples.max(1), seed),
+            _ => return vec![*self],
+        };
+
+        let mut rng = Rng::for_point(seed, point);
+        let sample_intensity = intensity / (samples * samples) as f64;
+
+        stratified(samples, &mut rng)
+            .into_iter()
+            .map(|s| {
+                let offset = match shape {
+                    AreaShape::Rectangle { u, v } => (s.x - 0.5) * u + (s.y - 0.5) * v,
+                    AreaShape::Sphere { radius } => {
+                        // A sphere looks like a disk from any point, so spread
+                        // the samples evenly over that disk.
+                        let w = (point - position).normalize_or_zero();
+                        let a = if w.x.abs() > 0.9 { DVec3::Y } else { DVec3::X };
+                        let a = w.cross(a).normalize_or_zero();
+                        let b = w.cross(a);
+                        let r = radius * s.x.sqrt();
+                        let theta = 2.0 * PI * s.y;
+                        r * (theta.cos() * a + theta.sin() * b)
+                    }
+                };
+                Self::Point {
+                    intensity: sample_intensity,
+                    color,
+                    position: position + offset,
+                }
+            })
+            .collect()
+    }
+
+    /// The direction from `point` towards the light, along with the largest
+    /// `t` at which an object on that ray can shadow `point`. Ambient light
+    /// can't be shadowed, so it has no shadow ray.
+    pub fn shadow_ray(&self, point: DVec3) -> Option<(DVec3, f64)> {
+        match self {
+            Self::Ambient { .. } => None,
+            Self::Directional { direction, .. } => Some((*direction, f64::INFINITY)),
+            Self::Point { position, .. }
+            | Self::Spot { position, .. }
+            | Self::Area { position, .. } => Some((*position - point, 1.0)),
+        }
+    }
+
+    pub fn color(&self) -> DVec3 {
+        match self {
+            Self::Ambient { color, .. }
+            | Self::Directional { color, .. }
+            | Self::Point { color, .. }
+            | Self::Spot { color, .. }
+            | Self::Area { color, .. } => *color,
+        }
+    }
+
+    /// The light reaching `point` from this light, per color channel, as a
+    /// factor to scale the surface color by. Area lights are treated as a
+    /// point light at their center here; use `Light::sample` first to get
+    /// their soft edges.
+    pub fn compute_lighting(
+        &self,
+        point: DVec3,
+        normal: DVec3,
+        view: DVec3,
+        specular: i32,
+    ) -> DVec3 {
+        self.compute_intensity(point, normal, view, specular) * self.color() / 255.0
+    }
+
+    fn compute_intensity(&self, point: DVec3, normal: DVec3, view: DVec3, specular: i32) -> f64 {
+        if let Self::Ambient { intensity, .. } = self {
             return *intensity;
         }
 
@@ -23,11 +178,41 @@ impl Light {
             Self::Directional {
                 intensity,
                 direction,
-            } => (*direction, intensity),
+                ..
+            } => (*direction, *intensity),
             Self::Point {
                 intensity,
                 position,
-            } => (*position - point, intensity),
+                ..
+            }
+            | Self::Area {
+                intensity,
+                position,
+                ..
+            } => (*position - point, *intensity),
+            Self::Spot {
+                intensity,
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                falloff,
+                ..
+            } => {
+                let l = *position - point;
+                let cos_angle = (-l).normalize().dot(direction.normalize());
+                let cos_inner = inner_angle.to_radians().cos();
+                let cos_outer = outer_angle.to_radians().cos();
+                let cone = if cos_inner > cos_outer {
+                    ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0)
+                } else if cos_angle >= cos_outer {
+                    // no fade at all, just a hard-edged cone
+                    1.0
+                } else {
+                    0.0
+                };
+                (l, intensity * cone.powf(*falloff))
+            }
             _ => unreachable!(),
         };
 
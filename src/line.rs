@@ -0,0 +1,238 @@
+use glam::DVec2;
+use serde::Deserialize;
+use std::ops::Range;
+
+/// How the ends of a line, and of each of its dashes, are finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum LineCap {
+    /// Square, right at the end point.
+    #[default]
+    Butt,
+    /// Square, half the thickness past the end point.
+    Square,
+    /// A half circle around the end point.
+    Round,
+}
+
+fn default_thickness() -> f64 {
+    1.0
+}
+
+fn default_anti_aliased() -> bool {
+    true
+}
+
+/// How to draw a line, in pixels.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LineStyle {
+    #[serde(default = "default_thickness")]
+    pub thickness: f64,
+    #[serde(default)]
+    pub cap: LineCap,
+    /// The lengths of the dashes and the gaps between them, taking turns
+    /// and starting with a dash. Empty for a solid line.
+    #[serde(default)]
+    pub dashes: Vec<f64>,
+    /// Whether to blend the line's edges smoothly into what's under it,
+    /// rather than every pixel being either all line or not at all.
+    #[serde(default = "default_anti_aliased")]
+    pub anti_aliased: bool,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            thickness: default_thickness(),
+            cap: LineCap::default(),
+            dashes: vec![],
+            anti_aliased: default_anti_aliased(),
+        }
+    }
+}
+
+// The parts of the line from `p0` to `p1` that are dashes, as the fractions
+// of the way along it that each starts and ends at.
+fn dashes(p0: DVec2, p1: DVec2, pattern: &[f64]) -> Vec<(f64, f64)> {
+    let length = p0.distance(p1);
+    let period: f64 = pattern.iter().map(|l| l.max(0.0)).sum();
+    if pattern.is_empty() || period <= 0.0 || length == 0.0 {
+        return vec![(0.0, 1.0)];
+    }
+
+    let mut dashes = vec![];
+    let mut start = 0.0;
+    'outer: loop {
+        for (i, l) in pattern.iter().enumerate() {
+            let end = (start + l.max(0.0)).min(length);
+            if i % 2 == 0 && end > start {
+                dashes.push((start / length, end / length));
+            }
+            start = end;
+            if start >= length {
+                break 'outer;
+            }
+        }
+    }
+    dashes
+}
+
+// Xiaolin Wu's line algorithm: calls `plot` with the two pixels straddling
+// the line in every column (or row, for a steep line), how far along the
+// line they are, and how much of each it covers. Pixel centers are at
+// whole numbers plus a half.
+fn wu(p0: DVec2, p1: DVec2, mut plot: impl FnMut(i32, i32, f64, f64)) {
+    let steep = (p1.y - p0.y).abs() > (p1.x - p0.x).abs();
+    // work with pixel centers on whole numbers, and x as the major axis
+    let swizzle = |p: DVec2| {
+        let p = p - DVec2::splat(0.5);
+        if steep {
+            DVec2::new(p.y, p.x)
+        } else {
+            p
+        }
+    };
+    let (a, b) = (swizzle(p0), swizzle(p1));
+    let (a, b, reversed) = if a.x <= b.x {
+        (a, b, false)
+    } else {
+        (b, a, true)
+    };
+    let dx = b.x - a.x;
+    let gradient = if dx == 0.0 { 1.0 } else { (b.y - a.y) / dx };
+    let t_at = |x: f64| {
+        let t = if dx == 0.0 {
+            0.0
+        } else {
+            ((x - a.x) / dx).clamp(0.0, 1.0)
+        };
+        if reversed {
+            1.0 - t
+        } else {
+            t
+        }
+    };
+    let mut put = |x: i32, y: f64, coverage: f64| {
+        let t = t_at(x as f64);
+        let (row, fraction) = (y.floor(), y - y.floor());
+        for (row, c) in [(row, 1.0 - fraction), (row + 1.0, fraction)] {
+            let (px, py) = if steep {
+                (row as i32, x)
+            } else {
+                (x, row as i32)
+            };
+            plot(px, py, t, c * coverage);
+        }
+    };
+
+    // The end pixels are only as covered as the line reaches into them.
+    let x_start = a.x.round();
+    let start_gap = 1.0 - (a.x + 0.5 - (a.x + 0.5).floor());
+    put(x_start as i32, a.y + gradient * (x_start - a.x), start_gap);
+    let x_end = b.x.round();
+    let end_gap = b.x + 0.5 - (b.x + 0.5).floor();
+    if x_end > x_start {
+        put(x_end as i32, b.y + gradient * (x_end - b.x), end_gap);
+    }
+
+    for x in (x_start as i32 + 1)..(x_end as i32) {
+        put(x, a.y + gradient * (x as f64 - a.x), 1.0);
+    }
+}
+
+// Calls `plot` with every pixel within `rows` and `columns` that a line
+// `style.thickness` wide from `p0` to `p1` covers, with its end caps, by
+// how far each pixel's center is from the line. Also says how far along the
+// line each pixel is.
+fn coverage(
+    p0: DVec2,
+    p1: DVec2,
+    style: &LineStyle,
+    columns: &Range<i32>,
+    rows: &Range<i32>,
+    mut plot: impl FnMut(i32, i32, f64, f64),
+) {
+    let half = style.thickness.max(1.0) / 2.0;
+    let length = p0.distance(p1);
+    let direction = if length > 0.0 {
+        (p1 - p0) / length
+    } else {
+        DVec2::X
+    };
+    let extension = match style.cap {
+        LineCap::Butt => 0.0,
+        LineCap::Square | LineCap::Round => half,
+    };
+
+    let reach = half + extension + 1.0;
+    let (min, max) = (
+        p0.min(p1) - DVec2::splat(reach),
+        p0.max(p1) + DVec2::splat(reach),
+    );
+    let x_range = (min.x.floor() as i32).max(columns.start)..(max.x.ceil() as i32).min(columns.end);
+    let y_range = (min.y.floor() as i32).max(rows.start)..(max.y.ceil() as i32).min(rows.end);
+
+    for y in y_range {
+        for x in x_range.clone() {
+            let offset = DVec2::new(x as f64 + 0.5, y as f64 + 0.5) - p0;
+            let along = offset.dot(direction);
+            let across = offset.perp_dot(direction).abs();
+            // how far outside the line the pixel's center is, negative inside
+            let outside = match style.cap {
+                LineCap::Butt | LineCap::Square => {
+                    let beyond_ends = (-extension - along).max(along - length - extension);
+                    (across - half).max(beyond_ends)
+                }
+                LineCap::Round => {
+                    let nearest = along.clamp(0.0, length);
+                    (offset - direction * nearest).length() - half
+                }
+            };
+            let c = if style.anti_aliased {
+                (0.5 - outside).clamp(0.0, 1.0)
+            } else if outside <= 0.0 {
+                1.0
+            } else {
+                0.0
+            };
+            if c > 0.0 {
+                let t = if length > 0.0 {
+                    (along / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                plot(x, y, t, c);
+            }
+        }
+    }
+}
+
+/// Calls `plot` with each pixel within `columns` and `rows` that a line in
+/// `style` from `p0` to `p1` covers at all, how far along the line it is
+/// from 0 at `p0` to 1 at `p1`, and how much of the pixel the line covers.
+/// Thin anti-aliased lines with butt ends are drawn with Xiaolin Wu's
+/// algorithm, and anything else by how far pixels are from the line.
+pub fn trace(
+    p0: DVec2,
+    p1: DVec2,
+    style: &LineStyle,
+    columns: Range<i32>,
+    rows: Range<i32>,
+    mut plot: impl FnMut(i32, i32, f64, f64),
+) {
+    for (start, end) in dashes(p0, p1, &style.dashes) {
+        let (a, b) = (p0.lerp(p1, start), p0.lerp(p1, end));
+        // from how far along the dash to how far along the whole line
+        let mut plot_dash = |x: i32, y: i32, t: f64, c: f64| {
+            if columns.contains(&x) && rows.contains(&y) {
+                plot(x, y, start + (end - start) * t, c);
+            }
+        };
+
+        if style.anti_aliased && style.thickness <= 1.0 && style.cap == LineCap::Butt {
+            let thickness = style.thickness.max(0.0);
+            wu(a, b, |x, y, t, c| plot_dash(x, y, t, c * thickness));
+        } else {
+            coverage(a, b, style, &columns, &rows, &mut plot_dash);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::texture::Texture;
+use glam::{DVec2, DVec3};
+use serde::Deserialize;
+
+fn default_color() -> DVec3 {
+    DVec3::splat(255.0)
+}
+
+fn default_specular() -> i32 {
+    -1
+}
+
+fn default_refractive_index() -> f64 {
+    1.0
+}
+
+/// How a surface responds to light. Its `color` defaults to white, which is
+/// handy when a texture or vertex colors give the real color. A `specular`
+/// exponent of -1 marks a matte surface with no highlights. `transparency`
+/// is the fraction of light that passes through the surface, bending by
+/// `refractive_index` relative to the air outside.
+///
+/// A textured surface has the index of its texture in the scene's list of
+/// textures, whose colors scale `color`. That's filled in from its model
+/// when the scene is built rather than read in directly.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Material {
+    #[serde(default = "default_color")]
+    pub color: DVec3,
+    #[serde(default = "default_specular")]
+    pub specular: i32,
+    #[serde(default)]
+    pub reflective: f64,
+    #[serde(default)]
+    pub transparency: f64,
+    #[serde(default = "default_refractive_index")]
+    pub refractive_index: f64,
+    #[serde(skip)]
+    pub texture: Option<usize>,
+}
+
+impl Material {
+    /// A matte, opaque material of the given color.
+    pub fn new(color: DVec3) -> Self {
+        Self {
+            color,
+            specular: default_specular(),
+            reflective: 0.0,
+            transparency: 0.0,
+            refractive_index: default_refractive_index(),
+            texture: None,
+        }
+    }
+
+    /// The surface color at the texture coordinates `uv`, for a sample with
+    /// the given `footprint` (see `Texture::sample`).
+    pub fn color_at(&self, uv: DVec2, footprint: [DVec2; 2], textures: &[Texture]) -> DVec3 {
+        match self.texture {
+            Some(texture) => self.color * textures[texture].sample(uv, footprint) / 255.0,
+            None => self.color,
+        }
+    }
+}
@@ -0,0 +1,534 @@
+use crate::bvh::Bvh;
+use crate::camera::Viewport;
+use crate::canvas::Canvas;
+use crate::error::SceneError;
+use crate::light::{reflect_ray, Light};
+use crate::material::Material;
+use crate::sampling::Sampling;
+use crate::scene::{load_objects, load_textures, Camera, Triangle};
+use crate::scene_definition::{load_scene, RayTracerSceneDefinition};
+use crate::section::Sections;
+use crate::texture::Texture;
+use glam::{DVec2, DVec3};
+use serde::Deserialize;
+use std::path::Path;
+
+type Color = DVec3;
+
+/// Bends the unit vector `direction` through a surface with unit `normal`
+/// facing back against it, where `eta` is the ratio of the refractive
+/// indices on either side. Returns `None` on total internal reflection.
+fn refract_ray(direction: DVec3, normal: DVec3, eta: f64) -> Option<DVec3> {
+    let cos_i = -normal.dot(direction);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    Some(eta * direction + (eta * cos_i - (1.0 - sin2_t).sqrt()) * normal)
+}
+
+/// Schlick's approximation of the fraction of light reflected rather than
+/// refracted when going from a medium with index `n1` into one with `n2`.
+fn schlick(cos_i: f64, n1: f64, n2: f64) -> f64 {
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    let cos = if n1 > n2 {
+        // past the critical angle everything is reflected
+        let sin2_t = (n1 / n2).powi(2) * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return 1.0;
+        }
+        (1.0 - sin2_t).sqrt()
+    } else {
+        cos_i
+    };
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Sphere {
+    pub center: DVec3,
+    pub radius: f64,
+    #[serde(flatten)]
+    pub material: Material,
+}
+
+impl Sphere {
+    fn intersect_ray_sphere(&self, origin: DVec3, d: DVec3) -> (f64, f64) {
+        let co = origin - self.center;
+
+        let a = d.dot(d);
+        let b = 2.0 * d.dot(co);
+        let c = co.dot(co) - self.radius * self.radius;
+
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return (f64::INFINITY, f64::INFINITY);
+        }
+
+        let t1 = (-b + discriminant.sqrt()) / (2.0 * a);
+        let t2 = (-b - discriminant.sqrt()) / (2.0 * a);
+
+        (t1, t2)
+    }
+}
+
+impl Triangle {
+    // Möller–Trumbore: solve for `t` and the barycentric coordinates of the
+    // hit point in one go.
+    fn intersect_ray_triangle(&self, origin: DVec3, d: DVec3) -> f64 {
+        let edge1 = self.v1 - self.v0;
+        let edge2 = self.v2 - self.v0;
+
+        let p = d.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f64::EPSILON {
+            // the ray is parallel to the triangle
+            return f64::INFINITY;
+        }
+        let inverse_determinant = 1.0 / determinant;
+
+        let s = origin - self.v0;
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return f64::INFINITY;
+        }
+
+        let q = s.cross(edge1);
+        let v = d.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return f64::INFINITY;
+        }
+
+        edge2.dot(q) * inverse_determinant
+    }
+
+    /// The weights of the three vertices that give `point`, which should be
+    /// in the triangle's plane.
+    fn barycentric(&self, point: DVec3) -> DVec3 {
+        let normal = (self.v1 - self.v0).cross(self.v2 - self.v0);
+        let area = |a: DVec3, b: DVec3| (b - a).cross(point - a).dot(normal);
+        DVec3::new(
+            area(self.v1, self.v2),
+            area(self.v2, self.v0),
+            area(self.v0, self.v1),
+        ) / normal.length_squared()
+    }
+}
+
+/// Anything a ray can hit.
+// Triangles carry all their vertex attributes and so are much bigger than
+// spheres, but boxing them would cost an extra pointer chase in every
+// intersection test.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Copy)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Triangle(Triangle),
+}
+
+impl Primitive {
+    /// The smallest `t` in `min_t..=max_t` at which the ray hits this
+    /// primitive, if any.
+    pub fn intersect(
+        &self,
+        origin: DVec3,
+        direction: DVec3,
+        min_t: f64,
+        max_t: f64,
+    ) -> Option<f64> {
+        // misses come back as an infinite `t`
+        let in_range = |t: f64| t.is_finite() && t >= min_t && t <= max_t;
+
+        match self {
+            Self::Sphere(sphere) => {
+                let (t1, t2) = sphere.intersect_ray_sphere(origin, direction);
+                [t1, t2]
+                    .into_iter()
+                    .filter(|t| in_range(*t))
+                    .reduce(f64::min)
+            }
+            Self::Triangle(triangle) => {
+                Some(triangle.intersect_ray_triangle(origin, direction)).filter(|t| in_range(*t))
+            }
+        }
+    }
+
+    /// The outward unit surface normal at `point`. For triangles, outward is
+    /// the side their vertices wind counter-clockwise around.
+    fn normal(&self, point: DVec3) -> DVec3 {
+        match self {
+            Self::Sphere(sphere) => (point - sphere.center).normalize(),
+            Self::Triangle(triangle) => (triangle.v1 - triangle.v0)
+                .cross(triangle.v2 - triangle.v0)
+                .normalize(),
+        }
+    }
+
+    /// The unit normal to light `point` with. That's the same as `normal`
+    /// for spheres, but triangles interpolate their vertex normals.
+    fn shading_normal(&self, point: DVec3) -> DVec3 {
+        match self {
+            Self::Sphere(_) => self.normal(point),
+            Self::Triangle(triangle) => {
+                let weights = triangle.barycentric(point);
+                let [n0, n1, n2] = triangle.normals;
+                (weights.x * n0 + weights.y * n1 + weights.z * n2)
+                    .try_normalize()
+                    .unwrap_or_else(|| self.normal(point))
+            }
+        }
+    }
+
+    /// The color of the surface at `point`, where the ray from `origin`
+    /// along `direction` hit it, taking its texture into account. See
+    /// `Scene::trace_ray` for `differentials`.
+    fn color(
+        &self,
+        origin: DVec3,
+        direction: DVec3,
+        point: DVec3,
+        differentials: [(DVec3, DVec3); 2],
+        textures: &[Texture],
+    ) -> DVec3 {
+        match self {
+            Self::Sphere(sphere) => sphere.material.color,
+            Self::Triangle(triangle) => {
+                let [uv0, uv1, uv2] = triangle.uvs;
+                let uv_at = |p: DVec3| {
+                    let weights = triangle.barycentric(p);
+                    weights.x * uv0 + weights.y * uv1 + weights.z * uv2
+                };
+                let uv = uv_at(point);
+                let weights = triangle.barycentric(point);
+                let [c0, c1, c2] = triangle.colors;
+                let vertex_color = (weights.x * c0 + weights.y * c1 + weights.z * c2) / 255.0;
+
+                // follow the neighbouring rays to the triangle's plane
+                let normal = (triangle.v1 - triangle.v0).cross(triangle.v2 - triangle.v0);
+                let footprint = differentials.map(|(d_origin, d_direction)| {
+                    let (from, along) = (origin + d_origin, direction + d_direction);
+                    let approach = normal.dot(along);
+                    if (d_origin, d_direction) == (DVec3::ZERO, DVec3::ZERO) || approach == 0.0 {
+                        return DVec2::ZERO;
+                    }
+                    let t = normal.dot(triangle.v0 - from) / approach;
+                    uv_at(from + t * along) - uv
+                });
+
+                triangle.material.color_at(uv, footprint, textures) * vertex_color
+            }
+        }
+    }
+
+    fn material(&self) -> &Material {
+        match self {
+            Self::Sphere(sphere) => &sphere.material,
+            Self::Triangle(triangle) => &triangle.material,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RayTracerSceneDefinition")]
+pub struct Scene {
+    pub primitives: Vec<Primitive>,
+    bvh: Bvh,
+    pub lights: Vec<Light>,
+    pub background: Color,
+    pub camera: Camera,
+    pub sampling: Sampling,
+    pub textures: Vec<Texture>,
+}
+
+impl TryFrom<RayTracerSceneDefinition> for Scene {
+    type Error = SceneError;
+
+    fn try_from(other: RayTracerSceneDefinition) -> Result<Self, Self::Error> {
+        let (textures, texture_indices) = load_textures(other.textures)?;
+        let spheres = other.spheres.into_iter().map(Primitive::Sphere);
+        // Meshes are intersected in world space, so bake each instance's
+        // transform into its triangles up front, and cut them there too.
+        let sections: Sections = other.sections.into();
+        let triangles = load_objects(other.instances, &other.models, &texture_indices)?
+            .into_iter()
+            .flat_map(|object| {
+                let planes = sections.and(&object.sections).planes;
+                object
+                    .triangles
+                    .into_iter()
+                    .flat_map(move |t| t.transform(&object.transform).section(&planes))
+                    .map(Primitive::Triangle)
+            });
+
+        let primitives: Vec<Primitive> = spheres.chain(triangles).collect();
+
+        Ok(Self {
+            bvh: Bvh::build(&primitives),
+            primitives,
+            lights: other.lights,
+            background: other.background,
+            camera: other.camera.try_into()?,
+            sampling: other.sampling,
+            textures,
+        })
+    }
+}
+
+impl Camera {
+    /// How far primary rays go, where the far plane is if there is one. Rays
+    /// reach the viewport at 1.
+    fn max_t(&self) -> f64 {
+        let Viewport { distance, far, .. } = self.viewport;
+        // how much nearer the viewport is than where rays start, along `z`
+        let depth = distance * self.eye.w - self.eye.z;
+        far.map_or(f64::INFINITY, |far| 1.0 + (far - distance) / depth)
+    }
+
+    /// The world-space origin and direction of the primary ray through the
+    /// canvas point `x`, `y`. Rays from a perspective camera all start at
+    /// the camera, and parallel ones from behind the viewport.
+    fn ray(&self, canvas: &Canvas, x: f64, y: f64) -> (DVec3, DVec3) {
+        let point = self.viewport.direction_from_canvas_point(canvas, x, y);
+        let direction = point * self.eye.w - self.eye.truncate();
+        (
+            self.transform.transform_point3(point - direction),
+            self.transform.transform_vector3(direction),
+        )
+    }
+}
+
+impl Scene {
+    /// Reads the ray tracer scene in the YAML file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        load_scene::<RayTracerSceneDefinition, _>(path.as_ref())
+    }
+
+    fn light_point(&self, point: DVec3, normal: DVec3, view: DVec3, specular: i32) -> DVec3 {
+        self.lights
+            .iter()
+            .flat_map(|light| light.sample(point))
+            .map(|light| {
+                let transmittance = match light.shadow_ray(point) {
+                    Some((direction, max_t)) => self.transmittance(point, direction, max_t),
+                    None => 1.0,
+                };
+                if transmittance <= 0.0 {
+                    return DVec3::ZERO;
+                }
+                transmittance * light.compute_lighting(point, normal, view, specular)
+            })
+            .fold(DVec3::ZERO, |total, lighting| total + lighting)
+    }
+
+    /// How much light makes it along a shadow ray, after passing through
+    /// every transparent surface in the way.
+    fn transmittance(&self, origin: DVec3, direction: DVec3, max_t: f64) -> f64 {
+        let mut transmittance = 1.0;
+        let mut min_t = 0.001;
+
+        while let Some((occluder, t)) = self.closest_intersection(origin, direction, min_t, max_t) {
+            transmittance *= occluder.material().transparency;
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+            min_t = t + 0.001;
+        }
+        transmittance
+    }
+
+    fn closest_intersection(
+        &self,
+        origin: DVec3,
+        direction: DVec3,
+        min_t: f64,
+        max_t: f64,
+    ) -> Option<(&Primitive, f64)> {
+        self.bvh
+            .closest_intersection(&self.primitives, origin, direction, min_t, max_t)
+    }
+
+    /// The color seen along the ray from `origin` in `direction`.
+    /// `differentials` are how `origin` and `direction` change from this ray
+    /// to the rays of the next samples across and up the screen, which sets
+    /// how blurry texture lookups are. Reflected and refracted rays get
+    /// zeros and see textures in full detail.
+    pub fn trace_ray(
+        &self,
+        origin: DVec3,
+        direction: DVec3,
+        differentials: [(DVec3, DVec3); 2],
+        min_t: f64,
+        max_t: f64,
+        recursion_depth: u8,
+    ) -> Color {
+        let (primitive, closest_t) =
+            match self.closest_intersection(origin, direction, min_t, max_t) {
+                Some(hit) => hit,
+                None => return self.background,
+            };
+        let material = primitive.material();
+
+        let point = origin + closest_t * direction;
+        // Shade whichever side of the surface the ray hit, and remember which
+        // it was so that refraction knows if it's entering or leaving.
+        let entering = primitive.normal(point).dot(direction) <= 0.0;
+        let outward_normal = primitive.shading_normal(point);
+        let normal = if entering {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        let intensity = self.light_point(point, normal, -direction, material.specular);
+        let local_color =
+            primitive.color(origin, direction, point, differentials, &self.textures) * intensity;
+
+        if (material.reflective <= 0.0 && material.transparency <= 0.0) || recursion_depth == 0 {
+            return local_color;
+        }
+        let r = reflect_ray(-direction, normal);
+        let reflected_color = self.trace_ray(
+            point,
+            r,
+            [(DVec3::ZERO, DVec3::ZERO); 2],
+            0.001,
+            f64::INFINITY,
+            recursion_depth - 1,
+        );
+
+        let surface_color = if material.transparency > 0.0 {
+            let (n1, n2) = if entering {
+                (1.0, material.refractive_index)
+            } else {
+                (material.refractive_index, 1.0)
+            };
+            let unit_direction = direction.normalize();
+            let fresnel = schlick(-normal.dot(unit_direction), n1, n2);
+
+            let transmitted_color = match refract_ray(unit_direction, normal, n1 / n2) {
+                Some(refracted) => {
+                    let refracted_color = self.trace_ray(
+                        point,
+                        refracted,
+                        [(DVec3::ZERO, DVec3::ZERO); 2],
+                        0.001,
+                        f64::INFINITY,
+                        recursion_depth - 1,
+                    );
+                    reflected_color * fresnel + refracted_color * (1.0 - fresnel)
+                }
+                None => reflected_color,
+            };
+
+            local_color * (1.0 - material.transparency) + transmitted_color * material.transparency
+        } else {
+            local_color
+        };
+
+        surface_color * (1.0 - material.reflective) + reflected_color * material.reflective
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    /// How many times a ray may bounce off reflective or refract through
+    /// transparent surfaces.
+    pub recursion_depth: u8,
+    /// How many worker threads to render with.
+    pub threads: usize,
+    /// Overrides the number of samples per side of each pixel set in the
+    /// scene's `sampling`.
+    pub samples: Option<u32>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            recursion_depth: 3,
+            threads: 1,
+            samples: None,
+        }
+    }
+}
+
+/// Ray traces `scene` into every pixel of `canvas`, casting rays from the
+/// camera through the viewport at each of the pixel's samples and blending
+/// them with the scene's reconstruction filter.
+pub fn render(scene: &Scene, canvas: &mut Canvas, options: RenderOptions) {
+    let sampling = Sampling {
+        samples: options.samples.unwrap_or(scene.sampling.samples),
+        ..scene.sampling
+    };
+    // how far apart neighbouring samples are, in pixels
+    let spacing = 2.0 * sampling.filter.radius() / sampling.samples.max(1) as f64;
+
+    canvas.draw_in_parallel(options.threads, |band| {
+        for cx in band.x_range() {
+            for cy in band.y_range() {
+                let (color, total_weight) = sampling.samples(cx, cy).into_iter().fold(
+                    (Color::ZERO, 0.0),
+                    |(color, total_weight), (offset, weight)| {
+                        let (x, y) = (cx as f64 + offset.x, cy as f64 + offset.y);
+                        let (origin, direction) = scene.camera.ray(band, x, y);
+                        let differentials = [(x + spacing, y), (x, y + spacing)].map(|(x, y)| {
+                            let (neighbour_origin, neighbour_direction) =
+                                scene.camera.ray(band, x, y);
+                            (neighbour_origin - origin, neighbour_direction - direction)
+                        });
+                        let sample = scene.trace_ray(
+                            origin,
+                            direction,
+                            differentials,
+                            1.0,
+                            scene.camera.max_t(),
+                            options.recursion_depth,
+                        );
+                        (color + sample * weight, total_weight + weight)
+                    },
+                );
+                band.put_pixel(cx, cy, color / total_weight);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reflective spheres over a floor, and a triangle across the middle.
+    const SCENE: &str = "
+background: [0, 0, 0]
+camera: {viewport: {width: 1, height: 1, distance: 1}}
+spheres:
+  - {center: [0, -1, 3], radius: 1, color: [255, 0, 0], specular: 500, reflective: 0.2}
+  - {center: [2, 0, 4], radius: 1, color: [0, 0, 255], specular: 500, reflective: 0.3}
+  - {center: [0, -5001, 0], radius: 5000, color: [255, 255, 0], reflective: 0.5}
+models:
+  triangle:
+    vertices: [[-2, -1, 5], [1, 1.5, 5], [-1, 0.5, 3]]
+    triangles: [{vertices: [0, 1, 2], color: [0, 255, 0]}]
+instances: [{model: triangle, transform: {}}]
+lights:
+  - {type: Ambient, intensity: 0.2}
+  - {type: Point, intensity: 0.6, position: [2, 1, 0]}
+sampling: {samples: 2, pattern: Jittered, seed: 3}
+";
+
+    #[test]
+    fn parallel_renders_match_the_single_threaded_one() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let render_with = |threads| {
+            let mut canvas = Canvas::new(40, 37);
+            let options = RenderOptions {
+                threads,
+                ..RenderOptions::default()
+            };
+            render(&scene, &mut canvas, options);
+            canvas.image()
+        };
+        let single = render_with(1);
+        for threads in [3, 8] {
+            assert!(single == render_with(threads), "{} threads", threads);
+        }
+    }
+}
@@ -1,33 +1,392 @@
-use crate::camera::Viewport;
 use crate::canvas::Canvas;
-use crate::scene::{Object, Scene, Triangle};
+use crate::line::LineStyle;
+use crate::scene::{Camera, Object, Scene, Triangle};
+use clap::ValueEnum;
+use glam::{DMat4, DVec2, DVec3};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// How the rasterizer lights triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+pub enum Shading {
+    /// Lights each triangle once, at its center, with its face normal.
+    #[default]
+    Flat,
+    /// Lights each vertex with its normal and interpolates the colors.
+    Gouraud,
+    /// Interpolates the vertex normals and lights every pixel.
+    Phong,
+}
+
+/// What the rasterizer draws of each triangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+pub enum RenderMode {
+    /// Shaded surfaces.
+    #[default]
+    Filled,
+    /// Every edge, whether or not something is in front of it.
+    Wireframe,
+    /// Shaded surfaces with their edges drawn over them.
+    FilledWithEdges,
+    /// Only the edges that aren't hidden behind a surface, like a line
+    /// drawing. Surfaces are filled with the background color.
+    HiddenLine,
+}
+
+fn default_edge_color() -> DVec3 {
+    DVec3::splat(255.0)
+}
+
+/// How edges are drawn, for the render modes that draw them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EdgeStyle {
+    #[serde(default = "default_edge_color")]
+    pub color: DVec3,
+    #[serde(flatten)]
+    pub line: LineStyle,
+}
+
+impl Default for EdgeStyle {
+    fn default() -> Self {
+        Self {
+            color: default_edge_color(),
+            line: LineStyle::default(),
+        }
+    }
+}
+
+// The light from each of the scene's lights reaching `point` in camera
+// space, as seen by its camera, as factors to scale the surface color by.
+fn light_contributions(scene: &Scene, point: DVec3, normal: DVec3, specular: i32) -> Vec<DVec3> {
+    let view = scene.camera.toward_eye(point);
+    scene
+        .lights
+        .iter()
+        .map(|light| light.compute_lighting(point, normal, view, specular))
+        .collect()
+}
+
+// Adds up the light from each of the scene's lights reaching `point`, less
+// whatever the light's shadow map says is blocked on the way. `face_normal`
+// is the triangle's own, as that's what the shadow maps saw.
+fn illuminate(
+    scene: &Scene,
+    contributions: impl IntoIterator<Item = DVec3>,
+    point: DVec3,
+    face_normal: DVec3,
+) -> DVec3 {
+    contributions
+        .into_iter()
+        .enumerate()
+        .map(|(i, lighting)| {
+            if lighting == DVec3::ZERO {
+                // no need to look for shadows where there's no light
+                lighting
+            } else {
+                lighting * scene.light_visibility(i, point, face_normal)
+            }
+        })
+        .fold(DVec3::ZERO, |acc, lighting| acc + lighting)
+}
+
+impl Camera {
+    /// The projection into clip space, which every scene the rasterizer
+    /// loads has.
+    pub fn projection(&self) -> DMat4 {
+        self.projection
+            .expect("rasterizer scenes are checked for a far plane")
+    }
+
+    /// Where `point`, in camera space, lands on `canvas`, with the depth the
+    /// depth buffer holds for it as `z`, along with its `w` in clip space.
+    /// The depth is OpenGL's window depth turned around, from 1 at the near
+    /// plane to 0 at the far one, so that an empty depth buffer is as far
+    /// away as can be.
+    pub fn project(&self, canvas: &Canvas, point: DVec3) -> (DVec3, f64) {
+        let clip = self.projection() * point.extend(1.0);
+        let ndc = clip.truncate() / clip.w;
+        let half_size = DVec2::new(canvas.width() as f64, canvas.height() as f64) / 2.0;
+        let window = (ndc.truncate() * half_size).extend((1.0 - ndc.z) / 2.0);
+        (window, clip.w)
+    }
+}
 
 impl Triangle {
-    pub fn render(&self, canvas: &mut Canvas, viewport: &Viewport) {
-        canvas.draw_filled_depth_triangle(
-            viewport.project_vertex(canvas, self.v0),
-            self.v0[2],
-            viewport.project_vertex(canvas, self.v1),
-            self.v1[2],
-            viewport.project_vertex(canvas, self.v2),
-            self.v2[2],
-            self.color,
-        );
+    pub fn render(&self, canvas: &mut Canvas, scene: &Scene) {
+        let material = &self.material;
+        let vertices = [self.v0, self.v1, self.v2];
+        let projected = vertices.map(|v| scene.camera.project(canvas, v));
+        let points = projected.map(|(point, _)| point);
+        let face_normal = self.face_normal();
+
+        // Flat shading lights the whole triangle as it is at its center,
+        // though shadows still fall on it pixel by pixel.
+        let flat_lighting = match scene.shading {
+            Shading::Flat => {
+                light_contributions(scene, self.center, face_normal, material.specular)
+            }
+            _ => vec![],
+        };
+
+        if scene.shading == Shading::Flat && self.is_uniform() && !scene.has_shadow_maps() {
+            // the whole triangle is one color
+            let lighting = illuminate(scene, flat_lighting, self.center, face_normal);
+            canvas.draw_filled_depth_triangle(
+                points[0],
+                points[1],
+                points[2],
+                material.color * self.colors[0] / 255.0 * lighting,
+            );
+            return;
+        }
+
+        // Every vertex carries its barycentric coordinates for Gouraud
+        // shading, to blend the lighting of the vertices with, or its normal
+        // for Phong shading, followed by its UV, its color and its position.
+        let attributes = [0, 1, 2].map(|i| {
+            let a = match scene.shading {
+                Shading::Flat => DVec3::ZERO,
+                Shading::Gouraud => DVec3::AXES[i],
+                Shading::Phong => self.normals[i],
+            };
+            let (uv, color, p) = (self.uvs[i], self.colors[i], vertices[i]);
+            [
+                a.x, a.y, a.z, uv.x, uv.y, color.x, color.y, color.z, p.x, p.y, p.z,
+            ]
+        });
+        let vertex_lighting = match scene.shading {
+            Shading::Gouraud => [0, 1, 2].map(|i| {
+                light_contributions(scene, vertices[i], self.normals[i], material.specular)
+            }),
+            _ => Default::default(),
+        };
+
+        let inverse_w = projected.map(|(_, w)| 1.0 / w);
+        canvas.draw_shaded_depth_triangle(points, inverse_w, attributes, |fragment| {
+            let [a, b, c, u, v, red, green, blue, x, y, z] = fragment.attributes;
+            let point = DVec3::new(x, y, z);
+            let lighting = match scene.shading {
+                Shading::Flat => {
+                    illuminate(scene, flat_lighting.iter().copied(), point, face_normal)
+                }
+                Shading::Gouraud => {
+                    let blend = |values: [DVec3; 3]| a * values[0] + b * values[1] + c * values[2];
+                    let contributions = (0..scene.lights.len())
+                        .map(|i| blend(vertex_lighting.each_ref().map(|lighting| lighting[i])));
+                    illuminate(scene, contributions, point, face_normal)
+                }
+                Shading::Phong => {
+                    let normal = DVec3::new(a, b, c).normalize_or_zero();
+                    let contributions =
+                        light_contributions(scene, point, normal, material.specular);
+                    illuminate(scene, contributions, point, face_normal)
+                }
+            };
+            let footprint = [fragment.d_dx, fragment.d_dy].map(|d| DVec2::new(d[3], d[4]));
+            let vertex_color = DVec3::new(red, green, blue) / 255.0;
+            material.color_at(DVec2::new(u, v), footprint, &scene.textures)
+                * vertex_color
+                * lighting
+        });
+    }
+
+    /// Fills the triangle with `color`, hiding whatever is behind it.
+    pub fn render_solid(&self, canvas: &mut Canvas, scene: &Scene, color: DVec3) {
+        let [p0, p1, p2] = [self.v0, self.v1, self.v2].map(|v| scene.camera.project(canvas, v).0);
+        canvas.draw_filled_depth_triangle(p0, p1, p2, color);
+    }
+
+    /// The ends of each of the triangle's edges that should be outlined.
+    pub fn outlined_edges(&self) -> Vec<(DVec3, DVec3)> {
+        let vertices = [self.v0, self.v1, self.v2];
+        (0..3)
+            .filter(|&i| self.edges[i])
+            .map(|i| (vertices[i], vertices[(i + 1) % 3]))
+            .collect()
+    }
+}
+
+// Outlines the edge from `a` to `b` in the scene's edge style, leaving out
+// the parts hidden behind what's already drawn if `depth_tested`.
+fn render_edge(canvas: &mut Canvas, scene: &Scene, (a, b): (DVec3, DVec3), depth_tested: bool) {
+    let EdgeStyle { color, line } = &scene.edges;
+    let [p0, p1] = [a, b].map(|v| scene.camera.project(canvas, v).0);
+    if depth_tested {
+        canvas.draw_depth_line(p0, p1, line, *color);
+    } else {
+        canvas.draw_styled_line(p0.truncate(), p1.truncate(), line, *color);
     }
 }
 
 impl Object {
-    pub fn render(&self, canvas: &mut Canvas, viewport: &Viewport) {
+    pub fn render(&self, canvas: &mut Canvas, scene: &Scene) {
+        self.render_with_caps(canvas, scene, |t, canvas| t.render(canvas, scene));
+    }
+
+    pub fn render_solid(&self, canvas: &mut Canvas, scene: &Scene, color: DVec3) {
+        self.render_with_caps(canvas, scene, |t, canvas| {
+            t.render_solid(canvas, scene, color)
+        });
+    }
+
+    // Draws each triangle with `draw`. A capped object has its cut surfaces
+    // drawn instead of the back faces they're in front of.
+    fn render_with_caps(
+        &self,
+        canvas: &mut Canvas,
+        scene: &Scene,
+        draw: impl Fn(&Triangle, &mut Canvas),
+    ) {
+        if self.sections.cap().is_none() {
+            for t in &self.triangles {
+                draw(t, canvas);
+            }
+            return;
+        }
+
+        let camera = &scene.camera;
+        for t in self.triangles.iter().filter(|t| t.is_front_facing(camera)) {
+            draw(t, canvas);
+        }
+        let caps = self.caps(camera);
+        if caps.is_empty() {
+            return;
+        }
+        // A cut surface is only there when the nearest of the object's own
+        // faces behind it is a back face, so draw them on their own to
+        // find out where that is.
+        let mut inside = canvas.blank();
         for t in &self.triangles {
-            t.render(canvas, viewport);
+            let color = if t.is_front_facing(camera) {
+                DVec3::ZERO
+            } else {
+                DVec3::splat(255.0)
+            };
+            t.render_solid(&mut inside, scene, color);
+        }
+        canvas.draw_masked(&inside, |canvas| {
+            for cap in &caps {
+                draw(cap, canvas);
+            }
+        });
+    }
+
+    /// Outlines the edges of the object's triangles. An edge two triangles
+    /// share is only drawn once, so that it doesn't get blended in twice.
+    /// Without the depth test nothing hides the back faces, so their edges
+    /// are drawn as well.
+    pub fn render_edges(&self, canvas: &mut Canvas, scene: &Scene, depth_tested: bool) {
+        let mut drawn = HashSet::new();
+        let faces = self
+            .triangles
+            .iter()
+            .filter(|t| !depth_tested || t.is_front_facing(&scene.camera));
+        for edge in faces.flat_map(Triangle::outlined_edges) {
+            let bits = |v: DVec3| v.to_array().map(f64::to_bits);
+            let (a, b) = (bits(edge.0), bits(edge.1));
+            if drawn.insert(if a < b { (a, b) } else { (b, a) }) {
+                render_edge(canvas, scene, edge, depth_tested);
+            }
         }
     }
 }
 
 impl Scene {
+    /// Draws the scene in its render mode. Edges go on after every surface
+    /// is in the depth buffer, so that only surfaces in front hide them.
     pub fn render(&self, canvas: &mut Canvas) {
         for object in &self.objects {
-            object.render(canvas, &self.camera.viewport);
+            match self.render_mode {
+                RenderMode::Filled | RenderMode::FilledWithEdges => object.render(canvas, self),
+                RenderMode::HiddenLine => object.render_solid(canvas, self, self.background),
+                RenderMode::Wireframe => {}
+            }
+        }
+        if self.render_mode != RenderMode::Filled {
+            for object in &self.objects {
+                object.render_edges(canvas, self, self.render_mode != RenderMode::Wireframe);
+            }
+        }
+    }
+
+    /// Renders on `threads` worker threads, each drawing every triangle into
+    /// its own band of the canvas. The result is identical to `render`.
+    pub fn render_in_parallel(&self, canvas: &mut Canvas, threads: usize) {
+        canvas.draw_in_parallel(threads, |band| self.render(band));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Multisampling;
+
+    // Two lit cubes, one in front of the other, at an angle so that their
+    // edges cross the bands' boundaries every which way.
+    const SCENE: &str = "
+models:
+  cube:
+    vertices: [[1, 1, 1], [-1, 1, 1], [-1, -1, 1], [1, -1, 1],
+               [1, 1, -1], [-1, 1, -1], [-1, -1, -1], [1, -1, -1]]
+    triangles:
+      - {vertices: [0, 1, 2], color: [255, 0, 0], specular: 50}
+      - {vertices: [0, 2, 3], color: [255, 0, 0], specular: 50}
+      - {vertices: [4, 0, 3], color: [0, 255, 0]}
+      - {vertices: [4, 3, 7], color: [0, 255, 0]}
+      - {vertices: [5, 4, 7], color: [0, 0, 255]}
+      - {vertices: [5, 7, 6], color: [0, 0, 255]}
+      - {vertices: [1, 5, 6], color: [255, 255, 0]}
+      - {vertices: [1, 6, 2], color: [255, 255, 0]}
+      - {vertices: [4, 5, 1], color: [255, 0, 255]}
+      - {vertices: [4, 1, 0], color: [255, 0, 255]}
+      - {vertices: [2, 6, 7], color: [0, 255, 255]}
+      - {vertices: [2, 7, 3], color: [0, 255, 255]}
+instances:
+  - {model: cube, transform: {translation: [0, 0, 5]}}
+  - {model: cube, transform: {translation: [1, 1, 3], rotation: {z: 20}}}
+camera:
+  transform: {translation: [-2, 0, -1], rotation: {y: 30, x: -10}}
+lights:
+  - {type: Ambient, intensity: 0.2}
+  - {type: Point, intensity: 0.6, position: [-3, 2, -2]}
+";
+
+    fn render(scene: &Scene, multisampling: Multisampling, threads: usize) -> Canvas {
+        let mut canvas = Canvas::with_multisampling(64, 61, multisampling);
+        canvas.fill(scene.background);
+        scene
+            .transform()
+            .section()
+            .cast_shadows()
+            .cull_back_faces()
+            .clip()
+            .render_in_parallel(&mut canvas, threads);
+        canvas
+    }
+
+    #[test]
+    fn parallel_renders_match_the_single_threaded_one() {
+        let mut scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        for render_mode in RenderMode::value_variants() {
+            for shading in Shading::value_variants() {
+                for multisampling in [Multisampling::Off, Multisampling::X4] {
+                    scene.render_mode = *render_mode;
+                    scene.shading = *shading;
+                    let single = render(&scene, multisampling, 1).image();
+                    // 3 threads make bands of 6 rows, with one short one
+                    for threads in [3, 8] {
+                        let parallel = render(&scene, multisampling, threads).image();
+                        assert!(
+                            single == parallel,
+                            "{:?} {:?} {:?} with {} threads",
+                            render_mode,
+                            shading,
+                            multisampling,
+                            threads
+                        );
+                    }
+                }
+            }
         }
     }
 }
@@ -0,0 +1,217 @@
+use glam::{DVec2, DVec3};
+use serde::Deserialize;
+
+/// A small seedable pseudo-random number generator (SplitMix64). We only
+/// need reproducible jitter, not statistical quality, and seeding one per
+/// pixel keeps renders identical however they're split across threads.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Seeds a generator from `seed` mixed with a pair of coordinates.
+    pub fn for_pixel(seed: u64, cx: i32, cy: i32) -> Self {
+        let mut rng = Self::new(seed ^ ((cx as u32 as u64) << 32 | cy as u32 as u64));
+        rng.next_u64();
+        rng
+    }
+
+    /// Seeds a generator from `seed` mixed with a point in space.
+    pub fn for_point(seed: u64, point: DVec3) -> Self {
+        let mut rng = Self::new(seed);
+        for coordinate in point.to_array() {
+            rng = Self::new(rng.next_u64() ^ coordinate.to_bits());
+        }
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly distributed value in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The centers of an `n`×`n` grid of cells covering the unit square.
+pub fn grid(n: u32) -> impl Iterator<Item = DVec2> {
+    let size = n as f64;
+    (0..n).flat_map(move |j| {
+        (0..n).map(move |i| DVec2::new((i as f64 + 0.5) / size, (j as f64 + 0.5) / size))
+    })
+}
+
+/// An `n`×`n` grid rotated by atan(1/n) and fitted into the unit square,
+/// so that splitting the square into `n²` rows and `n²` columns puts
+/// exactly one point in each row and each column.
+pub fn rotated_grid(n: u32) -> impl Iterator<Item = DVec2> {
+    let cells = n * n;
+    let size = cells as f64;
+    (0..n).flat_map(move |j| {
+        (0..n).map(move |i| {
+            // stepping along a row moves n columns right and one row up, and
+            // along a column n rows up and one column left
+            let column = i * n + n - 1 - j;
+            let row = j * n + i;
+            DVec2::new((column as f64 + 0.5) / size, (row as f64 + 0.5) / size)
+        })
+    })
+}
+
+/// One random point in each cell of an `n`×`n` grid covering the unit
+/// square.
+pub fn stratified(n: u32, rng: &mut Rng) -> Vec<DVec2> {
+    let size = n as f64;
+    (0..n)
+        .flat_map(|j| (0..n).map(move |i| (i, j)))
+        .map(|(i, j)| {
+            DVec2::new(
+                (i as f64 + rng.next_f64()) / size,
+                (j as f64 + rng.next_f64()) / size,
+            )
+        })
+        .collect()
+}
+
+/// Where in a pixel to place its samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum SamplePattern {
+    /// A regular grid.
+    #[default]
+    Grid,
+    /// A grid rotated by atan(1/n), so that no two samples line up in a row
+    /// or a column. This handles near-horizontal and near-vertical edges
+    /// much better than a regular grid with the same sample count.
+    RotatedGrid,
+    /// A random point in each grid cell, seeded per pixel.
+    Jittered,
+}
+
+/// How samples are weighted when combining them into a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum ReconstructionFilter {
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+}
+
+impl ReconstructionFilter {
+    /// How far from the pixel center, in pixels, samples are spread.
+    pub fn radius(&self) -> f64 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+        }
+    }
+
+    /// The weight of a sample `offset` pixels away from the pixel center.
+    pub fn weight(&self, offset: DVec2) -> f64 {
+        match self {
+            Self::Box => 1.0,
+            Self::Tent => (1.0 - offset.x.abs()).max(0.0) * (1.0 - offset.y.abs()).max(0.0),
+            // a standard deviation of half a pixel
+            Self::Gaussian => (-2.0 * offset.length_squared()).exp(),
+        }
+    }
+}
+
+fn default_samples() -> u32 {
+    1
+}
+
+/// Supersampling settings. Each pixel gets `samples`×`samples` samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Sampling {
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+    #[serde(default)]
+    pub pattern: SamplePattern,
+    #[serde(default)]
+    pub filter: ReconstructionFilter,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            samples: default_samples(),
+            pattern: SamplePattern::default(),
+            filter: ReconstructionFilter::default(),
+            seed: 0,
+        }
+    }
+}
+
+impl Sampling {
+    /// The sample positions for the pixel at `cx`, `cy`, as offsets in pixels
+    /// from its center, each with its filter weight.
+    pub fn samples(&self, cx: i32, cy: i32) -> Vec<(DVec2, f64)> {
+        let n = self.samples.max(1);
+        let unit_square: Vec<DVec2> = match self.pattern {
+            SamplePattern::Grid => grid(n).collect(),
+            SamplePattern::RotatedGrid => rotated_grid(n).collect(),
+            SamplePattern::Jittered => stratified(n, &mut Rng::for_pixel(self.seed, cx, cy)),
+        };
+
+        let spread = 2.0 * self.filter.radius();
+        unit_square
+            .into_iter()
+            .map(|p| {
+                let offset = (p - DVec2::splat(0.5)) * spread;
+                (offset, self.filter.weight(offset))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_grid_has_one_sample_in_each_row_and_column() {
+        let n = 4;
+        let cells = (n * n) as usize;
+        let points: Vec<DVec2> = rotated_grid(n).collect();
+        assert_eq!(points.len(), cells);
+
+        let (mut columns, mut rows) = (vec![0; cells], vec![0; cells]);
+        for p in &points {
+            assert!(p.cmpge(DVec2::ZERO).all() && p.cmplt(DVec2::ONE).all());
+            columns[(p.x * cells as f64) as usize] += 1;
+            rows[(p.y * cells as f64) as usize] += 1;
+        }
+        assert!(columns.iter().all(|&count| count == 1), "{:?}", columns);
+        assert!(rows.iter().all(|&count| count == 1), "{:?}", rows);
+    }
+
+    #[test]
+    fn rotated_samples_stay_spread_out() {
+        let sampling = Sampling {
+            samples: 4,
+            pattern: SamplePattern::RotatedGrid,
+            ..Sampling::default()
+        };
+        let offsets: Vec<DVec2> = sampling.samples(0, 0).into_iter().map(|(o, _)| o).collect();
+        // neighbours are a step of n columns and one row apart
+        let closest = offsets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| offsets[i + 1..].iter().map(move |b| a.distance(*b)))
+            .fold(f64::INFINITY, f64::min);
+        assert!(closest > 0.2, "{}", closest);
+    }
+}
@@ -1,28 +1,218 @@
 use crate::camera::Viewport;
+use crate::clip::Plane;
+use crate::error::SceneError;
+use crate::light::Light;
+use crate::material::Material;
+use crate::render::{EdgeStyle, RenderMode, Shading};
 use crate::scene_definition::{
-    CameraDefinition, InstanceDefinition, ModelDefinition, RotationDefinition, SceneDefinition,
+    load_scene, CameraDefinition, InstanceDefinition, ModelDefinition, RotationDefinition,
+    SceneDefinition, SectionPlaneDefinition, SectionsDefinition, TextureDefinition,
     TransformDefinition, TriangleDefinition,
 };
-use glam::{DMat4, DQuat, DVec3, EulerRot};
+use crate::section::Sections;
+use crate::shadow::{ShadowMap, Shadows};
+use crate::texture::Texture;
+use glam::{DMat4, DQuat, DVec2, DVec3, DVec4, EulerRot};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Triangle {
     pub v0: DVec3,
     pub v1: DVec3,
     pub v2: DVec3,
+    /// The surface normal at each vertex.
+    pub normals: [DVec3; 3],
+    /// The texture coordinates of each vertex, if the material has a texture.
+    pub uvs: [DVec2; 3],
+    /// The color of each vertex, which scales the material's color. They're
+    /// all white for a model without vertex colors.
+    pub colors: [DVec3; 3],
+    /// Whether to outline each edge, from each vertex to the next, when
+    /// drawing edges. Edges between coplanar faces and edges made by
+    /// clipping aren't outlined.
+    pub edges: [bool; 3],
+    pub material: Material,
+    /// The center of the whole triangle, which its pieces keep when it's
+    /// clipped, so that flat shading lights them all alike.
+    pub center: DVec3,
+}
+
+/// A corner of a triangle, with everything that gets interpolated across
+/// the triangle.
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub position: DVec3,
+    pub normal: DVec3,
+    pub uv: DVec2,
     pub color: DVec3,
 }
 
-impl From<(TriangleDefinition, &Vec<DVec3>)> for Triangle {
-    fn from((triangle, vertices): (TriangleDefinition, &Vec<DVec3>)) -> Self {
+impl Vertex {
+    /// The vertex `t` of the way from this one to `other`.
+    pub fn lerp(&self, other: &Self, t: f64) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            normal: self.normal.lerp(other.normal, t),
+            uv: self.uv.lerp(other.uv, t),
+            color: self.color.lerp(other.color, t),
+        }
+    }
+}
+
+impl Triangle {
+    pub fn vertices(&self) -> [Vertex; 3] {
+        let positions = [self.v0, self.v1, self.v2];
+        [0, 1, 2].map(|i| Vertex {
+            position: positions[i],
+            normal: self.normals[i],
+            uv: self.uvs[i],
+            color: self.colors[i],
+        })
+    }
+
+    /// A triangle with the same material and edges as this one but different
+    /// corners.
+    pub fn with_vertices(&self, [a, b, c]: [Vertex; 3]) -> Self {
         Self {
-            v0: vertices[triangle.vertices[0]],
-            v1: vertices[triangle.vertices[1]],
-            v2: vertices[triangle.vertices[2]],
-            color: triangle.color,
+            v0: a.position,
+            v1: b.position,
+            v2: c.position,
+            normals: [a.normal, b.normal, c.normal],
+            uvs: [a.uv, b.uv, c.uv],
+            colors: [a.color, b.color, c.color],
+            edges: self.edges,
+            material: self.material,
+            center: self.center,
+        }
+    }
+
+    /// The unit normal of the triangle's plane, facing the side from which
+    /// its vertices go round counterclockwise.
+    pub fn face_normal(&self) -> DVec3 {
+        (self.v1 - self.v0)
+            .cross(self.v2 - self.v0)
+            .normalize_or_zero()
+    }
+
+    /// Whether the triangle is the same color all over before lighting,
+    /// without a texture or vertex colors varying across it.
+    pub fn is_uniform(&self) -> bool {
+        self.material.texture.is_none() && self.colors.iter().all(|c| *c == self.colors[0])
+    }
+}
+
+// Looks up a triangle's three `indices` into one of its model's lists.
+fn lookup<T: Copy>(
+    list: &[T],
+    indices: [usize; 3],
+    name: &'static str,
+) -> Result<[T; 3], SceneError> {
+    let item = |i: usize| {
+        list.get(indices[i])
+            .copied()
+            .ok_or_else(|| SceneError::IndexOutOfRange {
+                path: format!("{}[{}]", name, i),
+                index: indices[i],
+                len: list.len(),
+                list: name,
+            })
+    };
+    Ok([item(0)?, item(1)?, item(2)?])
+}
+
+impl TryFrom<(TriangleDefinition, &ModelDefinition)> for Triangle {
+    type Error = SceneError;
+
+    fn try_from(
+        (triangle, model): (TriangleDefinition, &ModelDefinition),
+    ) -> Result<Self, Self::Error> {
+        let [v0, v1, v2] = lookup(&model.vertices, triangle.vertices, "vertices")?;
+        let normals = match triangle.normals {
+            Some(indices) => lookup(&model.normals, indices, "normals")?.map(|n| n.normalize()),
+            None => [(v1 - v0).cross(v2 - v0).normalize_or_zero(); 3],
+        };
+        let uvs = match triangle.uvs {
+            Some(indices) => lookup(&model.uvs, indices, "uvs")?,
+            None => [DVec2::ZERO; 3],
+        };
+        let colors = if model.colors.is_empty() {
+            [DVec3::splat(255.0); 3]
+        } else {
+            lookup(&model.colors, triangle.vertices, "colors")?
+        };
+
+        Ok(Self {
+            v0,
+            v1,
+            v2,
+            normals,
+            uvs,
+            colors,
+            edges: [true; 3],
+            material: triangle.material,
+            center: (v0 + v1 + v2) / 3.0,
+        })
+    }
+}
+
+// Gives the triangles without normals of their own the average of the face
+// normals around each of their vertices, weighted by area. Faces that meet
+// the triangle at more than the model's crease angle are left out, so hard
+// edges stay hard.
+fn smooth_normals(model: &ModelDefinition, triangles: &mut [Triangle]) {
+    let cos_crease = model.crease_angle.to_radians().cos();
+    let faces: Vec<DVec3> = triangles
+        .iter()
+        .map(|t| (t.v1 - t.v0).cross(t.v2 - t.v0))
+        .collect();
+
+    let mut faces_around_vertex = vec![vec![]; model.vertices.len()];
+    for (i, triangle) in model.triangles.iter().enumerate() {
+        for &v in &triangle.vertices {
+            faces_around_vertex[v].push(i);
+        }
+    }
+
+    for (i, (definition, triangle)) in model.triangles.iter().zip(triangles).enumerate() {
+        if definition.normals.is_some() {
+            continue;
+        }
+        let face = faces[i].normalize_or_zero();
+        triangle.normals = definition.vertices.map(|v| {
+            faces_around_vertex[v]
+                .iter()
+                .map(|&j| faces[j])
+                .filter(|n| n.normalize_or_zero().dot(face) >= cos_crease)
+                .fold(DVec3::ZERO, |acc, n| acc + n)
+                .normalize_or_zero()
+        });
+    }
+}
+
+// Stops outlining the edges where two triangles lie flat against each other,
+// like the diagonal across each face of a cube.
+fn hide_coplanar_edges(model: &ModelDefinition, triangles: &mut [Triangle]) {
+    let mut triangles_along_edge: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+    for (i, triangle) in model.triangles.iter().enumerate() {
+        for k in 0..3 {
+            let (a, b) = (triangle.vertices[k], triangle.vertices[(k + 1) % 3]);
+            triangles_along_edge
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((i, k));
+        }
+    }
+
+    for sides in triangles_along_edge.values() {
+        if let [(i, k), (j, l)] = sides[..] {
+            if triangles[i].face_normal().dot(triangles[j].face_normal()) > 1.0 - 1e-9 {
+                triangles[i].edges[k] = false;
+                triangles[j].edges[l] = false;
+            }
         }
     }
 }
@@ -43,7 +233,7 @@ impl From<TransformDefinition> for DMat4 {
         Self::from_scale_rotation_translation(
             DVec3::splat(other.scale),
             other.rotation.into(),
-            other.translation,
+            other.translation.unwrap_or_default(),
         )
     }
 }
@@ -54,11 +244,102 @@ pub struct Object {
     pub transform: DMat4,
     pub bounding_center: DVec3,
     pub bounding_radius: f64,
+    /// Cuts just this object. Once `Scene::section` has cut it, these are
+    /// all the sections it was cut with, the scene's included.
+    pub sections: Sections,
+}
+
+impl From<SectionPlaneDefinition> for Plane {
+    fn from(other: SectionPlaneDefinition) -> Self {
+        let normal = other.normal.normalize_or_zero();
+        Self::new(normal, -normal.dot(other.point))
+    }
+}
+
+impl From<SectionsDefinition> for Sections {
+    fn from(other: SectionsDefinition) -> Self {
+        Self {
+            planes: other.planes.into_iter().map(Plane::from).collect(),
+            cap: other.cap,
+        }
+    }
 }
 
-impl From<(InstanceDefinition, &HashMap<String, ModelDefinition>)> for Object {
-    fn from((instance, models): (InstanceDefinition, &HashMap<String, ModelDefinition>)) -> Self {
-        let model = models.get(&instance.model).unwrap();
+impl TryFrom<TextureDefinition> for Texture {
+    type Error = SceneError;
+
+    fn try_from(other: TextureDefinition) -> Result<Self, Self::Error> {
+        let image = image::open(&other.path).map_err(|source| SceneError::Image {
+            path: "path".to_string(),
+            file: other.path.clone(),
+            source,
+        })?;
+        Ok(Self::new(
+            image.to_rgb8(),
+            other.filter,
+            other.wrap,
+            other.anisotropy,
+        ))
+    }
+}
+
+/// Loads every texture, along with a map from each texture's name to its
+/// index in the list, which is what materials refer to them by.
+pub fn load_textures(
+    definitions: HashMap<String, TextureDefinition>,
+) -> Result<(Vec<Texture>, HashMap<String, usize>), SceneError> {
+    let mut definitions: Vec<_> = definitions.into_iter().collect();
+    definitions.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut textures = vec![];
+    let mut indices = HashMap::new();
+    for (name, definition) in definitions {
+        let texture =
+            Texture::try_from(definition).map_err(|e| e.within(&format!("textures.{}", name)))?;
+        indices.insert(name, textures.len());
+        textures.push(texture);
+    }
+    Ok((textures, indices))
+}
+
+// Problems with the instance itself are reported relative to the instance,
+// while problems with its model are reported at the model's own path.
+impl
+    TryFrom<(
+        InstanceDefinition,
+        &HashMap<String, ModelDefinition>,
+        &HashMap<String, usize>,
+    )> for Object
+{
+    type Error = SceneError;
+
+    fn try_from(
+        (instance, models, textures): (
+            InstanceDefinition,
+            &HashMap<String, ModelDefinition>,
+            &HashMap<String, usize>,
+        ),
+    ) -> Result<Self, Self::Error> {
+        let model = models
+            .get(&instance.model)
+            .ok_or_else(|| SceneError::UnknownModel {
+                path: "model".to_string(),
+                model: instance.model.clone(),
+            })?;
+        let model_path = format!("models.{}", instance.model);
+
+        if model.vertices.is_empty() {
+            return Err(SceneError::EmptyModel {
+                path: format!("{}.vertices", model_path),
+            });
+        }
+        if !model.colors.is_empty() && model.colors.len() != model.vertices.len() {
+            return Err(SceneError::VertexColorCount {
+                path: format!("{}.colors", model_path),
+                colors: model.colors.len(),
+                vertices: model.vertices.len(),
+            });
+        }
 
         let mut bounding_center: DVec3 = model.vertices.iter().sum();
         bounding_center /= model.vertices.len() as f64;
@@ -66,22 +347,44 @@ impl From<(InstanceDefinition, &HashMap<String, ModelDefinition>)> for Object {
             .vertices
             .iter()
             .map(|v| (*v - bounding_center).length_squared())
-            .reduce(f64::max)
-            .unwrap()
+            .fold(0.0, f64::max)
             .sqrt();
 
         let transform: DMat4 = instance.transform.into();
 
-        Self {
-            triangles: model
-                .triangles
-                .iter()
-                .map(|t| Triangle::from((*t, &model.vertices)))
-                .collect(),
+        let mut triangles = model
+            .triangles
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                Triangle::try_from((*t, model))
+                    .map_err(|e| e.within(&format!("{}.triangles[{}]", model_path, i)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        smooth_normals(model, &mut triangles);
+        hide_coplanar_edges(model, &mut triangles);
+
+        if let Some(name) = &model.texture {
+            let texture = *textures
+                .get(name)
+                .ok_or_else(|| SceneError::UnknownTexture {
+                    path: format!("{}.texture", model_path),
+                    texture: name.clone(),
+                })?;
+            for (definition, triangle) in model.triangles.iter().zip(&mut triangles) {
+                if definition.uvs.is_some() {
+                    triangle.material.texture = Some(texture);
+                }
+            }
+        }
+
+        Ok(Self {
+            triangles,
             transform,
             bounding_center,
             bounding_radius,
-        }
+            sections: instance.sections.into(),
+        })
     }
 }
 
@@ -89,33 +392,125 @@ impl From<(InstanceDefinition, &HashMap<String, ModelDefinition>)> for Object {
 pub struct Camera {
     pub transform: DMat4,
     pub viewport: Viewport,
+    /// From camera space into clip space, if there's a far plane to bound a
+    /// parallel projection with. Only the rasterizer needs it.
+    pub projection: Option<DMat4>,
+    /// Where rays come from, as `Projection::eye`.
+    pub eye: DVec4,
 }
 
-impl From<CameraDefinition> for Camera {
-    fn from(other: CameraDefinition) -> Self {
-        Self {
-            transform: other.transform.into(),
+impl TryFrom<CameraDefinition> for Camera {
+    type Error = SceneError;
+
+    fn try_from(other: CameraDefinition) -> Result<Self, Self::Error> {
+        let translation = match (other.position, other.transform.translation) {
+            (Some(_), Some(_)) => {
+                return Err(SceneError::CameraPositionAndTranslation {
+                    path: "camera.position".to_string(),
+                })
+            }
+            (position, translation) => position.or(translation),
+        };
+        let transform = TransformDefinition {
+            translation,
+            ..other.transform
+        };
+        Ok(Self {
+            transform: DMat4::from(transform) * DMat4::from_quat(other.projection.rotation()),
             viewport: other.viewport,
-        }
+            projection: other.projection.matrix(&other.viewport),
+            eye: other.projection.eye(),
+        })
     }
 }
 
+/// Builds an object for every instance, reporting the first one that
+/// doesn't make sense.
+pub fn load_objects(
+    instances: Vec<InstanceDefinition>,
+    models: &HashMap<String, ModelDefinition>,
+    textures: &HashMap<String, usize>,
+) -> Result<Vec<Object>, SceneError> {
+    instances
+        .into_iter()
+        .enumerate()
+        .map(|(i, instance)| {
+            Object::try_from((instance, models, textures)).map_err(|e| match e {
+                SceneError::UnknownModel { .. } => e.within(&format!("instances[{}]", i)),
+                e => e,
+            })
+        })
+        .collect()
+}
+
 #[derive(Debug, Clone, Deserialize)]
-#[serde(from = "SceneDefinition")]
+#[serde(try_from = "SceneDefinition")]
 pub struct Scene {
     pub objects: Vec<Object>,
     pub camera: Camera,
+    pub lights: Vec<Light>,
+    pub shading: Shading,
+    pub render_mode: RenderMode,
+    pub edges: EdgeStyle,
+    pub background: DVec3,
+    pub shadows: Shadows,
+    /// The shadow map of each light, once `Scene::cast_shadows` has made
+    /// them. `None` for a light that doesn't cast shadows.
+    pub shadow_maps: Arc<[Option<ShadowMap>]>,
+    /// Cuts every object, once `Scene::section` applies them.
+    pub sections: Sections,
+    // shared, so the pipeline stages don't copy every image
+    pub textures: Arc<[Texture]>,
 }
 
-impl From<SceneDefinition> for Scene {
-    fn from(other: SceneDefinition) -> Self {
+impl TryFrom<SceneDefinition> for Scene {
+    type Error = SceneError;
+
+    fn try_from(other: SceneDefinition) -> Result<Self, Self::Error> {
+        let camera = Camera::try_from(other.camera)?;
+        if camera.projection.is_none() {
+            return Err(SceneError::NoFarPlane {
+                path: "camera.viewport".to_string(),
+            });
+        }
+        let (textures, texture_indices) = load_textures(other.textures)?;
+        Ok(Self {
+            objects: load_objects(other.instances, &other.models, &texture_indices)?,
+            camera,
+            lights: other.lights,
+            shading: other.shading,
+            render_mode: other.render_mode,
+            edges: other.edges,
+            background: other.background,
+            shadows: other.shadows,
+            shadow_maps: Arc::new([]),
+            sections: other.sections.into(),
+            textures: textures.into(),
+        })
+    }
+}
+
+impl Scene {
+    /// Reads the rasterizer scene in the YAML file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        load_scene::<SceneDefinition, _>(path.as_ref())
+    }
+
+    /// The same scene with `objects` in place of its own, for the pipeline
+    /// stages that rebuild the objects and pass everything else along.
+    pub fn with_objects(&self, objects: Vec<Object>) -> Self {
         Self {
-            objects: other
-                .instances
-                .into_iter()
-                .map(|instance| (instance, &other.models).into())
-                .collect(),
-            camera: other.camera.into(),
+            objects,
+            camera: self.camera,
+            lights: self.lights.clone(),
+            shading: self.shading,
+            render_mode: self.render_mode,
+            edges: self.edges.clone(),
+            background: self.background,
+            shadows: self.shadows,
+            shadow_maps: self.shadow_maps.clone(),
+            sections: self.sections.clone(),
+            textures: self.textures.clone(),
         }
     }
 }
@@ -1,19 +1,57 @@
-use glam::DVec3;
+use glam::{DVec2, DVec3};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use crate::camera::Viewport;
+use crate::camera::{Projection, Viewport};
+use crate::error::SceneError;
+use crate::light::Light;
+use crate::material::Material;
+use crate::raytracer::Sphere;
+use crate::render::{EdgeStyle, RenderMode, Shading};
+use crate::sampling::Sampling;
+use crate::shadow::Shadows;
+use crate::texture::{TextureFilter, WrapMode};
 
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub struct TriangleDefinition {
     pub vertices: [usize; 3],
-    pub color: DVec3,
+    /// Indices into the model's `normals` for each vertex, like the normal
+    /// indices of an OBJ face.
+    #[serde(default)]
+    pub normals: Option<[usize; 3]>,
+    /// Indices into the model's `uvs` for each vertex. Only triangles with
+    /// UVs get the model's texture.
+    #[serde(default)]
+    pub uvs: Option<[usize; 3]>,
+    #[serde(flatten)]
+    pub material: Material,
+}
+
+fn default_crease_angle() -> f64 {
+    60.0
 }
 
+/// A triangle mesh. Triangles without `normals` of their own get smooth
+/// normals generated for them, which stay sharp where faces meet at more
+/// than `crease_angle` degrees. `texture` names one of the scene's textures.
+/// `colors`, if there are any, go with `vertices` one for one and scale
+/// the color of the triangles around them, blending across each triangle.
 #[derive(Debug, Clone, Deserialize)]
 pub struct ModelDefinition {
     pub vertices: Vec<DVec3>,
+    #[serde(default)]
+    pub normals: Vec<DVec3>,
+    #[serde(default)]
+    pub uvs: Vec<DVec2>,
+    #[serde(default)]
+    pub colors: Vec<DVec3>,
+    #[serde(default)]
+    pub texture: Option<String>,
     pub triangles: Vec<TriangleDefinition>,
+    #[serde(default = "default_crease_angle")]
+    pub crease_angle: f64,
 }
 
 #[derive(Debug, Clone, Copy, Default, Deserialize)]
@@ -36,26 +74,175 @@ pub struct TransformDefinition {
     pub scale: f64,
     #[serde(default)]
     pub rotation: RotationDefinition,
+    /// `None` if the scene doesn't give one, which leaves things where they
+    /// are.
+    #[serde(default)]
+    pub translation: Option<DVec3>,
+}
+
+impl Default for TransformDefinition {
+    fn default() -> Self {
+        Self {
+            scale: default_scale(),
+            rotation: RotationDefinition::default(),
+            translation: None,
+        }
+    }
+}
+
+fn default_anisotropy() -> u32 {
+    1
+}
+
+/// An image file to texture models with. A relative `path` is relative to
+/// the scene file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TextureDefinition {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub filter: TextureFilter,
+    #[serde(default)]
+    pub wrap: WrapMode,
+    #[serde(default = "default_anisotropy")]
+    pub anisotropy: u32,
+}
+
+/// A world-space plane through `point`, cutting away everything on the
+/// side `normal` points away from.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SectionPlaneDefinition {
+    pub normal: DVec3,
     #[serde(default)]
-    pub translation: DVec3,
+    pub point: DVec3,
+}
+
+/// Planes to cut models open with, for cutaway views. The rasterizer fills
+/// in the cut surfaces of closed models in the `cap` color, if there is
+/// one; the ray tracer leaves them open.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SectionsDefinition {
+    #[serde(default)]
+    pub planes: Vec<SectionPlaneDefinition>,
+    #[serde(default)]
+    pub cap: Option<DVec3>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct InstanceDefinition {
     pub model: String,
     pub transform: TransformDefinition,
+    /// Cuts just this instance, on top of the scene's own sections. Its
+    /// `cap` takes the place of the scene's.
+    #[serde(default)]
+    pub sections: SectionsDefinition,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub struct CameraDefinition {
+    #[serde(default)]
     pub transform: TransformDefinition,
+    /// Where the camera is, as ray tracer scenes gave it before cameras had
+    /// a `transform`. It takes the place of `transform.translation`, so a
+    /// camera can't have both.
+    #[serde(default)]
+    pub position: Option<DVec3>,
     #[serde(default)]
     pub viewport: Viewport,
+    #[serde(default)]
+    pub projection: Projection,
+}
+
+/// Without any lights, a scene is shown in its plain colors.
+fn default_lights() -> Vec<Light> {
+    vec![Light::Ambient {
+        intensity: 1.0,
+        color: DVec3::splat(255.0),
+    }]
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SceneDefinition {
+    #[serde(default)]
+    pub textures: HashMap<String, TextureDefinition>,
+    pub models: HashMap<String, ModelDefinition>,
+    pub instances: Vec<InstanceDefinition>,
+    pub camera: CameraDefinition,
+    #[serde(default = "default_lights")]
+    pub lights: Vec<Light>,
+    #[serde(default)]
+    pub shading: Shading,
+    #[serde(default)]
+    pub render_mode: RenderMode,
+    #[serde(default)]
+    pub edges: EdgeStyle,
+    #[serde(default)]
+    pub background: DVec3,
+    #[serde(default)]
+    pub shadows: Shadows,
+    /// Cuts every instance.
+    #[serde(default)]
+    pub sections: SectionsDefinition,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RayTracerSceneDefinition {
+    #[serde(default)]
+    pub spheres: Vec<Sphere>,
+    #[serde(default)]
+    pub textures: HashMap<String, TextureDefinition>,
+    #[serde(default)]
     pub models: HashMap<String, ModelDefinition>,
+    #[serde(default)]
     pub instances: Vec<InstanceDefinition>,
+    pub lights: Vec<Light>,
+    pub background: DVec3,
     pub camera: CameraDefinition,
+    #[serde(default)]
+    pub sampling: Sampling,
+    #[serde(default)]
+    pub sections: SectionsDefinition,
+}
+
+/// What a scene file holds, for either renderer.
+pub trait SceneFile: DeserializeOwned {
+    fn textures(&mut self) -> &mut HashMap<String, TextureDefinition>;
+}
+
+impl SceneFile for SceneDefinition {
+    fn textures(&mut self) -> &mut HashMap<String, TextureDefinition> {
+        &mut self.textures
+    }
+}
+
+impl SceneFile for RayTracerSceneDefinition {
+    fn textures(&mut self) -> &mut HashMap<String, TextureDefinition> {
+        &mut self.textures
+    }
+}
+
+/// Makes relative texture paths relative to the directory of `scene_file`
+/// rather than to wherever we happen to be running from.
+fn resolve_texture_paths(textures: &mut HashMap<String, TextureDefinition>, scene_file: &Path) {
+    let directory = scene_file.parent().unwrap_or_else(|| Path::new(""));
+    for texture in textures.values_mut() {
+        texture.path = directory.join(&texture.path);
+    }
+}
+
+/// Reads a scene definition from the YAML file at `path` and builds the
+/// scene it describes.
+pub fn load_scene<T: SceneFile, S: TryFrom<T, Error = SceneError>>(
+    path: &Path,
+) -> Result<S, SceneError> {
+    let contents = std::fs::read(path).map_err(|source| SceneError::Io {
+        file: path.to_path_buf(),
+        source,
+    })?;
+    let mut definition: T =
+        serde_yaml::from_slice(&contents).map_err(|source| SceneError::Yaml {
+            file: path.to_path_buf(),
+            source,
+        })?;
+    resolve_texture_paths(definition.textures(), path);
+    definition.try_into()
 }
@@ -0,0 +1,228 @@
+use crate::clip::Plane;
+use crate::material::Material;
+use crate::scene::{Camera, Object, Scene, Triangle, Vertex};
+use glam::DVec3;
+
+/// Planes that cut away part of every object they apply to, and the color
+/// to cap the cut surfaces with, if any.
+#[derive(Debug, Clone, Default)]
+pub struct Sections {
+    pub planes: Vec<Plane>,
+    pub cap: Option<DVec3>,
+}
+
+impl Sections {
+    /// These sections along with `other`'s, capped in `other`'s color if it
+    /// has one.
+    pub fn and(&self, other: &Self) -> Self {
+        Self {
+            planes: self.planes.iter().chain(&other.planes).copied().collect(),
+            cap: other.cap.or(self.cap),
+        }
+    }
+
+    /// The color to cap the cut surfaces in, if there are any to cap.
+    pub fn cap(&self) -> Option<DVec3> {
+        self.cap.filter(|_| !self.planes.is_empty())
+    }
+}
+
+impl Triangle {
+    /// The pieces of the triangle that the planes keep.
+    pub fn section(&self, planes: &[Plane]) -> Vec<Self> {
+        planes.iter().fold(vec![*self], |pieces, plane| {
+            pieces
+                .iter()
+                .flat_map(|t| t.clip_against_plane(*plane))
+                .collect()
+        })
+    }
+
+    // The triangle moved along the lines of sight of `camera` onto `plane`,
+    // which for a back face showing through a cut there is the cut surface
+    // in front of it. It's turned around to face the viewer.
+    fn cap_on(&self, plane: &Plane, camera: &Camera, color: DVec3) -> Self {
+        let onto_plane = |point: DVec3| {
+            let sight = camera.toward_eye(point);
+            point - sight * (plane.signed_distance(point) / plane.normal.dot(sight))
+        };
+        let [a, b, c] = self.vertices().map(|v| Vertex {
+            position: onto_plane(v.position),
+            normal: -plane.normal,
+            color: DVec3::splat(255.0),
+            ..v
+        });
+        Self {
+            edges: [false; 3],
+            material: Material::new(color),
+            center: (a.position + b.position + c.position) / 3.0,
+            ..self.with_vertices([a, c, b])
+        }
+    }
+}
+
+impl Object {
+    /// The cut surfaces that the object's back faces show through, in
+    /// camera space, if it's capped. They're clipped to what `camera` sees
+    /// as well as to the other sections.
+    pub fn caps(&self, camera: &Camera) -> Vec<Triangle> {
+        let Some(color) = self.sections.cap() else {
+            return vec![];
+        };
+        let planes = &self.sections.planes;
+        let back_faces: Vec<&Triangle> = self
+            .triangles
+            .iter()
+            .filter(|t| !t.is_front_facing(camera))
+            .collect();
+
+        let clipping_planes = camera.clipping_planes();
+        let mut caps = vec![];
+        for (i, plane) in planes.iter().enumerate() {
+            if plane.normal.dot(camera.eye.truncate()) + plane.d * camera.eye.w >= 0.0 {
+                // the viewer is on the kept side, so can't see into the cut
+                continue;
+            }
+            let others: Vec<Plane> = planes
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, p)| *p)
+                .chain(clipping_planes.iter().copied())
+                .collect();
+            caps.extend(
+                back_faces
+                    .iter()
+                    .flat_map(|t| t.cap_on(plane, camera, color).section(&others)),
+            );
+        }
+        caps
+    }
+
+    fn section(&self, scene_sections: &Sections) -> Option<Self> {
+        let sections = scene_sections.and(&self.sections);
+        Some(Self {
+            sections: sections.clone(),
+            ..self.clip(&sections.planes)?
+        })
+    }
+}
+
+impl Scene {
+    /// Cuts every object with the scene's sections and its own. Every
+    /// object then carries all the sections it was cut with, to cap the cut
+    /// surfaces with.
+    pub fn section(&self) -> Self {
+        let objects = self
+            .objects
+            .iter()
+            .filter_map(|obj| obj.section(&self.sections))
+            .collect();
+        Self {
+            sections: Sections::default(),
+            ..self.with_objects(objects)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    // A cube 2 wide straight ahead of the camera, from 4 to 6 away, cut in
+    // half at 5 by `sections`.
+    fn scene(sections: &str) -> Scene {
+        let yaml = format!(
+            "
+models:
+  cube:
+    vertices: [[1, 1, 1], [-1, 1, 1], [-1, -1, 1], [1, -1, 1],
+               [1, 1, -1], [-1, 1, -1], [-1, -1, -1], [1, -1, -1]]
+    triangles:
+      - {{vertices: [0, 1, 2]}}
+      - {{vertices: [0, 2, 3]}}
+      - {{vertices: [4, 0, 3]}}
+      - {{vertices: [4, 3, 7]}}
+      - {{vertices: [5, 4, 7]}}
+      - {{vertices: [5, 7, 6]}}
+      - {{vertices: [1, 5, 6]}}
+      - {{vertices: [1, 6, 2]}}
+      - {{vertices: [4, 5, 1]}}
+      - {{vertices: [4, 1, 0]}}
+      - {{vertices: [2, 6, 7]}}
+      - {{vertices: [2, 7, 3]}}
+instances:
+  - {{model: cube, transform: {{translation: [0, 0, 5]}}}}
+camera: {{}}
+sections: {}
+",
+            sections
+        );
+        serde_yaml::from_str::<Scene>(&yaml)
+            .unwrap()
+            .transform()
+            .section()
+    }
+
+    fn area(t: &Triangle) -> f64 {
+        (t.v1 - t.v0).cross(t.v2 - t.v0).length() / 2.0
+    }
+
+    #[test]
+    fn sectioned_triangles_keep_what_every_plane_keeps() {
+        let triangle = Triangle {
+            v0: DVec3::ZERO,
+            v1: DVec3::new(2.0, 0.0, 0.0),
+            v2: DVec3::new(0.0, 2.0, 0.0),
+            center: DVec3::new(2.0, 2.0, 0.0) / 3.0,
+            ..scene("{}").objects[0].triangles[0]
+        };
+        // keep `x <= 1` and `y <= 1`, which leaves a square with a corner
+        // cut off
+        let planes = [Plane::new(-DVec3::X, 1.0), Plane::new(-DVec3::Y, 1.0)];
+        let pieces = triangle.section(&planes);
+        let kept: f64 = pieces.iter().map(area).sum();
+        assert!((kept - 1.0).abs() < EPSILON, "{}", kept);
+        for piece in &pieces {
+            for v in [piece.v0, piece.v1, piece.v2] {
+                assert!(planes.iter().all(|p| p.signed_distance(v) > -EPSILON));
+            }
+        }
+        assert!(triangle.section(&[Plane::new(DVec3::X, -3.0)]).is_empty());
+    }
+
+    #[test]
+    fn caps_fill_the_cut_facing_the_viewer() {
+        // keep the far half, so the camera looks in through the cut
+        let scene = scene("{planes: [{normal: [0, 0, 1], point: [0, 0, 5]}], cap: [255, 0, 0]}");
+        let object = &scene.objects[0];
+        for t in &object.triangles {
+            assert!([t.v0, t.v1, t.v2].iter().all(|v| v.z > 5.0 - EPSILON));
+        }
+
+        let caps = object.caps(&scene.camera);
+        assert!(!caps.is_empty());
+        for cap in &caps {
+            assert!([cap.v0, cap.v1, cap.v2]
+                .iter()
+                .all(|v| (v.z - 5.0).abs() < EPSILON));
+            assert!(cap.is_front_facing(&scene.camera));
+            assert_eq!(cap.material.color, DVec3::new(255.0, 0.0, 0.0));
+        }
+        // the back faces seen through the cut cover it exactly once
+        let covered: f64 = caps.iter().map(area).sum();
+        assert!((covered - 4.0).abs() < EPSILON, "{}", covered);
+    }
+
+    #[test]
+    fn caps_are_only_seen_through_cuts_facing_the_viewer() {
+        // keep the near half, whose own front face hides the cut
+        let near = scene("{planes: [{normal: [0, 0, -1], point: [0, 0, 5]}], cap: [255, 0, 0]}");
+        assert!(near.objects[0].caps(&near.camera).is_empty());
+        // and without a cap color there's nothing to cap with
+        let uncapped = scene("{planes: [{normal: [0, 0, 1], point: [0, 0, 5]}]}");
+        assert!(uncapped.objects[0].caps(&uncapped.camera).is_empty());
+    }
+}
@@ -0,0 +1,468 @@
+use crate::canvas::Canvas;
+use crate::clip::Plane;
+use crate::light::Light;
+use crate::scene::{Scene, Triangle};
+use glam::{DMat3, DMat4, DVec2, DVec3};
+use serde::Deserialize;
+
+/// How close to a light a shadow map's view starts. Anything nearer than
+/// this neither casts nor receives shadows from that light.
+const NEAR: f64 = 1e-3;
+
+/// Spot lights whose cone is at most this wide, in degrees from its axis,
+/// get a single perspective shadow map. Wider ones get a cube map like a
+/// point light, since one view can't cover them without losing detail.
+const MAX_SPOT_ANGLE: f64 = 60.0;
+
+/// The most the bias is scaled up by on surfaces the light only grazes.
+const MAX_SLOPE: f64 = 10.0;
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_resolution() -> u32 {
+    1024
+}
+
+fn default_bias() -> f64 {
+    0.01
+}
+
+fn default_pcf() -> u32 {
+    1
+}
+
+/// Shadow mapping settings for the rasterizer.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Shadows {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// The width and height of each shadow map, in texels.
+    #[serde(default = "default_resolution")]
+    pub resolution: u32,
+    /// How far behind the surface nearest to a light a point may be and
+    /// still be lit by it, in scene units, so that surfaces don't shadow
+    /// themselves. Surfaces the light hits at a slant get more, in
+    /// proportion to how much of them one texel of the map covers.
+    #[serde(default = "default_bias")]
+    pub bias: f64,
+    /// Percentage-closer filtering: how many texels out in each direction to
+    /// also test and average over, which softens the edges of shadows.
+    /// Zero tests just the one texel.
+    #[serde(default = "default_pcf")]
+    pub pcf: u32,
+}
+
+impl Default for Shadows {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            resolution: default_resolution(),
+            bias: default_bias(),
+            pcf: default_pcf(),
+        }
+    }
+}
+
+/// How a shadow map's view projects light space onto its canvas. Light
+/// space has the light shining along `z`.
+#[derive(Debug, Clone, Copy)]
+enum Projection {
+    /// Parallel rays, covering `center ± half_size` across and `near..far`
+    /// along `z`.
+    Orthographic {
+        center: DVec2,
+        half_size: DVec2,
+        near: f64,
+        far: f64,
+    },
+    /// Rays from the origin, out to `tan_half_angle` across for every unit
+    /// along `z`.
+    Perspective { tan_half_angle: f64 },
+}
+
+impl Projection {
+    // Where `point` lands on a canvas `resolution` texels wide, in canvas
+    // coordinates.
+    fn canvas_point(&self, point: DVec3, resolution: u32) -> DVec2 {
+        let normalized = match *self {
+            Self::Orthographic {
+                center, half_size, ..
+            } => (point.truncate() - center) / half_size,
+            Self::Perspective { tan_half_angle } => point.truncate() / (point.z * tan_half_angle),
+        };
+        normalized * (resolution / 2) as f64
+    }
+
+    // The value the depth buffer holds for a surface at `z`, which like
+    // the camera's depth is bigger the nearer it is, and changes
+    // linearly across the canvas.
+    fn depth(&self, z: f64) -> f64 {
+        match *self {
+            Self::Orthographic { near, far, .. } => 1.0 + (far - z) / (far - near),
+            Self::Perspective { .. } => 1.0 / z,
+        }
+    }
+
+    // The `z` of the surface whose depth buffer value is `depth`.
+    fn z(&self, depth: f64) -> f64 {
+        match *self {
+            Self::Orthographic { near, far, .. } => far - (depth - 1.0) * (far - near),
+            Self::Perspective { .. } => 1.0 / depth,
+        }
+    }
+
+    // How much of a surface at `z` facing the light one texel covers.
+    fn texel_size(&self, z: f64, resolution: u32) -> f64 {
+        match *self {
+            Self::Orthographic { half_size, .. } => {
+                2.0 * half_size.max_element() / resolution as f64
+            }
+            Self::Perspective { tan_half_angle } => 2.0 * tan_half_angle * z / resolution as f64,
+        }
+    }
+
+    // The planes bounding what the view sees, in light space. An
+    // orthographic view is fitted around everything, so it needs none.
+    fn clipping_planes(&self) -> Vec<Plane> {
+        match *self {
+            Self::Orthographic { .. } => vec![],
+            Self::Perspective { tan_half_angle: t } => vec![
+                Plane::new(DVec3::Z, -NEAR),
+                Plane::new(DVec3::new(1.0, 0.0, t).normalize(), 0.0),
+                Plane::new(DVec3::new(-1.0, 0.0, t).normalize(), 0.0),
+                Plane::new(DVec3::new(0.0, 1.0, t).normalize(), 0.0),
+                Plane::new(DVec3::new(0.0, -1.0, t).normalize(), 0.0),
+            ],
+        }
+    }
+
+    fn contains(&self, point: DVec3) -> bool {
+        match *self {
+            Self::Orthographic { .. } => true,
+            Self::Perspective { tan_half_angle } => {
+                point.z >= NEAR && point.truncate().abs().max_element() <= tan_half_angle * point.z
+            }
+        }
+    }
+}
+
+// The transform from camera space into a light space looking along
+// `direction` from `origin`.
+fn look_along(origin: DVec3, direction: DVec3) -> DMat4 {
+    let w = direction.normalize();
+    let up = if w.y.abs() > 0.9 { DVec3::X } else { DVec3::Y };
+    let u = up.cross(w).normalize();
+    let v = w.cross(u);
+    DMat4::from_mat3(DMat3::from_cols(u, v, w).transpose()) * DMat4::from_translation(-origin)
+}
+
+/// One depth image of the scene as a light sees it.
+#[derive(Debug, Clone)]
+struct ShadowView {
+    /// From camera space into light space.
+    transform: DMat4,
+    projection: Projection,
+    depth: Canvas,
+}
+
+impl ShadowView {
+    fn new(transform: DMat4, projection: Projection, resolution: u32) -> Self {
+        Self {
+            transform,
+            projection,
+            depth: Canvas::new(resolution, resolution),
+        }
+    }
+
+    // An orthographic view along `direction`, fitted around `triangles`.
+    fn fitted(direction: DVec3, triangles: &[Triangle], resolution: u32) -> Option<Self> {
+        if triangles.is_empty() {
+            return None;
+        }
+        let transform = look_along(DVec3::ZERO, direction);
+        let (min, max) = triangles
+            .iter()
+            .flat_map(|t| [t.v0, t.v1, t.v2])
+            .map(|v| transform.transform_point3(v))
+            .fold(
+                (DVec3::splat(f64::INFINITY), DVec3::splat(f64::NEG_INFINITY)),
+                |(min, max), v| (min.min(v), max.max(v)),
+            );
+
+        // leave a little room, so that nothing lands exactly on the edge
+        let margin = (max - min).max_element() * 0.01 + NEAR;
+        let projection = Projection::Orthographic {
+            center: ((min + max) / 2.0).truncate(),
+            half_size: ((max - min) / 2.0).truncate() + DVec2::splat(margin),
+            near: min.z - margin,
+            far: max.z + margin,
+        };
+        Some(Self::new(transform, projection, resolution))
+    }
+
+    fn draw(&mut self, triangles: &[Triangle]) {
+        let resolution = self.depth.width();
+        let planes = self.projection.clipping_planes();
+        for triangle in triangles {
+            let mut pieces = vec![triangle.transform(&self.transform)];
+            for plane in &planes {
+                pieces = pieces
+                    .iter()
+                    .flat_map(|t| t.clip_against_plane(*plane))
+                    .collect();
+            }
+
+            for piece in pieces {
+                let [p0, p1, p2] = [piece.v0, piece.v1, piece.v2].map(|v| {
+                    let point = self.projection.canvas_point(v, resolution);
+                    point.extend(self.projection.depth(v.z))
+                });
+                self.depth
+                    .draw_filled_depth_triangle(p0, p1, p2, DVec3::ZERO);
+            }
+        }
+    }
+
+    // How much of the light reaches `point`, from 0 to 1.
+    fn visibility(&self, point: DVec3, normal: DVec3, settings: &Shadows) -> f64 {
+        let point = self.transform.transform_point3(point);
+        if !self.projection.contains(point) {
+            return 1.0;
+        }
+
+        let resolution = self.depth.width();
+        let radius = settings.pcf as i32;
+        // A slanted surface drifts further from the light with every texel
+        // out, as far as the farthest texel filtering looks at.
+        let normal = self.transform.transform_vector3(normal).normalize_or_zero();
+        let cos = normal.z.abs().max(1.0 / MAX_SLOPE);
+        let slope = (1.0 - cos * cos).sqrt() / cos;
+        let texels = (radius + 1) as f64;
+        let bias = settings.bias + slope * texels * self.projection.texel_size(point.z, resolution);
+
+        let texel = self
+            .projection
+            .canvas_point(point, resolution)
+            .floor()
+            .as_ivec2();
+        let mut lit = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let depth = self.depth.depth(texel.x + dx, texel.y + dy);
+                // an empty texel has nothing between it and the light
+                if depth.is_none_or(|d| d == 0.0 || point.z <= self.projection.z(d) + bias) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f64 / ((2 * radius + 1) * (2 * radius + 1)) as f64
+    }
+}
+
+/// What a light can see of the scene, for telling which points it lights.
+/// Directional lights see it through one orthographic view fitted around
+/// the whole scene, narrow spot lights through one perspective view, and
+/// point and area lights, and wide spot lights, through six making up a
+/// cube around them.
+#[derive(Debug, Clone)]
+pub struct ShadowMap {
+    views: Vec<ShadowView>,
+    settings: Shadows,
+}
+
+impl ShadowMap {
+    /// Renders the shadow map for `light` from `triangles`, all in camera
+    /// space, or returns `None` for a light that casts no shadows.
+    pub fn new(light: &Light, triangles: &[Triangle], settings: Shadows) -> Option<Self> {
+        let resolution = settings.resolution.max(1);
+        // The faces of a cube map overlap by a few texels, so that filtering
+        // near the edge of one never looks off the edge of its map.
+        let overlap = 2.0 * (settings.pcf + 2) as f64 / resolution as f64;
+        let cube = |position: DVec3| {
+            [
+                DVec3::X,
+                -DVec3::X,
+                DVec3::Y,
+                -DVec3::Y,
+                DVec3::Z,
+                -DVec3::Z,
+            ]
+            .map(|direction| {
+                let projection = Projection::Perspective {
+                    tan_half_angle: 1.0 + overlap,
+                };
+                ShadowView::new(look_along(position, direction), projection, resolution)
+            })
+            .to_vec()
+        };
+
+        let mut views = match *light {
+            Light::Ambient { .. } => return None,
+            Light::Directional { direction, .. } => {
+                vec![ShadowView::fitted(-direction, triangles, resolution)?]
+            }
+            Light::Spot {
+                position,
+                direction,
+                outer_angle,
+                ..
+            } if outer_angle <= MAX_SPOT_ANGLE => {
+                let projection = Projection::Perspective {
+                    tan_half_angle: outer_angle.to_radians().tan(),
+                };
+                vec![ShadowView::new(
+                    look_along(position, direction),
+                    projection,
+                    resolution,
+                )]
+            }
+            Light::Point { position, .. }
+            | Light::Spot { position, .. }
+            | Light::Area { position, .. } => cube(position),
+        };
+
+        for view in &mut views {
+            view.draw(triangles);
+        }
+        Some(Self { views, settings })
+    }
+
+    /// How much of the light reaches `point` on a surface facing `normal`,
+    /// from 0 in full shadow to 1.
+    pub fn visibility(&self, point: DVec3, normal: DVec3) -> f64 {
+        // the view the point is most nearly straight ahead of
+        let view = self.views.iter().max_by(|a, b| {
+            let z = |view: &ShadowView| view.transform.transform_point3(point).z;
+            z(a).total_cmp(&z(b))
+        });
+        view.map_or(1.0, |view| view.visibility(point, normal, &self.settings))
+    }
+}
+
+impl Scene {
+    /// Renders a shadow map for every light, if the scene has shadows. It
+    /// needs every triangle in the scene, as things out of view can still
+    /// cast shadows into it, so this goes after transforming into camera
+    /// space but before culling or clipping anything.
+    pub fn cast_shadows(&self) -> Self {
+        let shadow_maps: Vec<Option<ShadowMap>> = if self.shadows.enabled {
+            let triangles: Vec<Triangle> = self
+                .objects
+                .iter()
+                .flat_map(|object| object.triangles.iter().copied())
+                .collect();
+            self.lights
+                .iter()
+                .map(|light| ShadowMap::new(light, &triangles, self.shadows))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Self {
+            shadow_maps: shadow_maps.into(),
+            ..self.clone()
+        }
+    }
+
+    /// How much of the light from `self.lights[light]` reaches `point` on a
+    /// surface facing `normal`, going by the scene's shadow maps. Without a
+    /// shadow map, that's all of it.
+    pub fn light_visibility(&self, light: usize, point: DVec3, normal: DVec3) -> f64 {
+        match self.shadow_maps.get(light) {
+            Some(Some(shadow_map)) => shadow_map.visibility(point, normal),
+            _ => 1.0,
+        }
+    }
+
+    /// Whether any light has a shadow map.
+    pub fn has_shadow_maps(&self) -> bool {
+        self.shadow_maps.iter().any(Option::is_some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+
+    // A square on the plane `y`, `half_size` out from the `y` axis, facing up.
+    fn square(y: f64, half_size: f64) -> [Triangle; 2] {
+        let corner = |x: f64, z: f64| DVec3::new(x, y, z) * DVec3::new(half_size, 1.0, half_size);
+        let triangle = |v0, v1, v2| Triangle {
+            v0,
+            v1,
+            v2,
+            normals: [DVec3::Y; 3],
+            uvs: [DVec2::ZERO; 3],
+            colors: [DVec3::ONE; 3],
+            edges: [true; 3],
+            material: Material::new(DVec3::ONE),
+            center: (v0 + v1 + v2) / 3.0,
+        };
+        let [a, b, c, d] =
+            [(-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0), (1.0, -1.0)].map(|(x, z)| corner(x, z));
+        [triangle(a, b, c), triangle(a, c, d)]
+    }
+
+    // A floor 8 wide, with a 2 wide square hanging over its middle.
+    fn triangles() -> Vec<Triangle> {
+        [square(0.0, 4.0), square(2.0, 1.0)].concat()
+    }
+
+    fn settings(pcf: u32) -> Shadows {
+        Shadows {
+            resolution: 256,
+            pcf,
+            ..Shadows::default()
+        }
+    }
+
+    fn point_light() -> Light {
+        Light::Point {
+            intensity: 1.0,
+            color: DVec3::ONE,
+            position: DVec3::new(0.0, 4.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn occluded_points_are_in_shadow() {
+        let directional = Light::Directional {
+            intensity: 1.0,
+            color: DVec3::ONE,
+            direction: DVec3::Y,
+        };
+        for light in [point_light(), directional] {
+            let map = ShadowMap::new(&light, &triangles(), settings(1)).unwrap();
+            // under the square
+            assert_eq!(map.visibility(DVec3::new(0.2, 0.0, -0.3), DVec3::Y), 0.0);
+            // out from under it, and on top of it, which shadows nothing
+            assert_eq!(map.visibility(DVec3::new(3.0, 0.0, 3.0), DVec3::Y), 1.0);
+            assert_eq!(map.visibility(DVec3::new(0.5, 2.0, 0.5), DVec3::Y), 1.0);
+        }
+    }
+
+    #[test]
+    fn filtering_softens_the_edge_of_a_shadow() {
+        // The point light throws the square's edge at `x = 1` onto the floor
+        // at `x = 2`.
+        let edge = DVec3::new(2.0, 0.0, 0.0);
+        let hard = ShadowMap::new(&point_light(), &triangles(), settings(0)).unwrap();
+        let soft = ShadowMap::new(&point_light(), &triangles(), settings(2)).unwrap();
+        assert!([0.0, 1.0].contains(&hard.visibility(edge, DVec3::Y)));
+        let visibility = soft.visibility(edge, DVec3::Y);
+        assert!(visibility > 0.0 && visibility < 1.0, "{}", visibility);
+    }
+
+    #[test]
+    fn ambient_lights_cast_no_shadows() {
+        let ambient = Light::Ambient {
+            intensity: 1.0,
+            color: DVec3::ONE,
+        };
+        assert!(ShadowMap::new(&ambient, &triangles(), settings(1)).is_none());
+    }
+}
@@ -0,0 +1,282 @@
+use glam::{DVec2, DVec3};
+use image::{ImageBuffer, RgbImage};
+use serde::Deserialize;
+
+/// How a texture is sampled between texel centers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum TextureFilter {
+    /// The nearest texel.
+    Nearest,
+    /// A blend of the four nearest texels.
+    #[default]
+    Bilinear,
+    /// A blend of bilinear samples from the two mip levels whose texels are
+    /// closest in size to what the sample covers, which keeps distant and
+    /// sharply angled surfaces from shimmering.
+    Trilinear,
+}
+
+/// What a texture does with UVs outside `0.0..1.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum WrapMode {
+    /// The texture tiles.
+    #[default]
+    Repeat,
+    /// The edge texels stretch on forever.
+    Clamp,
+}
+
+impl WrapMode {
+    fn wrap(&self, i: i64, size: u32) -> u32 {
+        match self {
+            Self::Repeat => i.rem_euclid(size as i64) as u32,
+            Self::Clamp => i.clamp(0, size as i64 - 1) as u32,
+        }
+    }
+}
+
+// Halves the image in each direction, down to a single pixel, by averaging
+// blocks of 2×2 pixels. Odd sizes repeat their last row or column.
+fn downsample(image: &RgbImage) -> RgbImage {
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut sum = [0u32; 3];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel((2 * x + dx).min(width - 1), (2 * y + dy).min(height - 1));
+            for c in 0..3 {
+                sum[c] += pixel[c] as u32;
+            }
+        }
+        image::Rgb(sum.map(|s| ((s + 2) / 4) as u8))
+    })
+}
+
+/// An image to look colors up in by UV coordinates. `(0, 0)` is the
+/// bottom left of the image and `(1, 1)` the top right.
+///
+/// Trilinear filtering takes up to `anisotropy` samples along the longer
+/// side of a stretched footprint, rather than blurring it to the size of
+/// that side.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    /// The mip chain: the image itself, then each level half the size of the
+    /// one before, down to 1×1.
+    levels: Vec<RgbImage>,
+    pub filter: TextureFilter,
+    pub wrap: WrapMode,
+    pub anisotropy: u32,
+}
+
+impl Texture {
+    pub fn new(image: RgbImage, filter: TextureFilter, wrap: WrapMode, anisotropy: u32) -> Self {
+        let mut levels = vec![image];
+        while let Some(level) = levels.last().filter(|l| l.width() > 1 || l.height() > 1) {
+            levels.push(downsample(level));
+        }
+
+        Self {
+            levels,
+            filter,
+            wrap,
+            anisotropy,
+        }
+    }
+
+    fn size(&self) -> DVec2 {
+        let (width, height) = self.levels[0].dimensions();
+        DVec2::new(width as f64, height as f64)
+    }
+
+    // The texel in column `x` and row `y` counting up from the bottom of mip
+    // `level`, wrapped into the image.
+    fn texel(&self, level: usize, x: i64, y: i64) -> DVec3 {
+        let image = &self.levels[level];
+        let (width, height) = image.dimensions();
+        let x = self.wrap.wrap(x, width);
+        let y = height - 1 - self.wrap.wrap(y, height);
+        let [r, g, b] = image.get_pixel(x, y).0;
+        DVec3::new(r as f64, g as f64, b as f64)
+    }
+
+    // `uv` in texels of mip `level`, with texel centers at whole numbers.
+    fn texel_position(&self, level: usize, uv: DVec2) -> DVec2 {
+        let (width, height) = self.levels[level].dimensions();
+        uv * DVec2::new(width as f64, height as f64) - DVec2::splat(0.5)
+    }
+
+    fn nearest(&self, level: usize, uv: DVec2) -> DVec3 {
+        let p = self.texel_position(level, uv).round();
+        self.texel(level, p.x as i64, p.y as i64)
+    }
+
+    fn bilinear(&self, level: usize, uv: DVec2) -> DVec3 {
+        let p = self.texel_position(level, uv);
+        let base = p.floor();
+        let t = p - base;
+        let (x, y) = (base.x as i64, base.y as i64);
+        let bottom = self
+            .texel(level, x, y)
+            .lerp(self.texel(level, x + 1, y), t.x);
+        let top = self
+            .texel(level, x, y + 1)
+            .lerp(self.texel(level, x + 1, y + 1), t.x);
+        bottom.lerp(top, t.y)
+    }
+
+    // Bilinear samples from the mip levels either side of the fractional
+    // level `lod`, blended together.
+    fn trilinear(&self, lod: f64, uv: DVec2) -> DVec3 {
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f64);
+        let level = lod.floor() as usize;
+        let t = lod - level as f64;
+        if t == 0.0 {
+            return self.bilinear(level, uv);
+        }
+        self.bilinear(level, uv)
+            .lerp(self.bilinear(level + 1, uv), t)
+    }
+
+    /// The color at `uv`, from 0 to 255 per channel like surface colors.
+    /// `footprint` is how far `uv` moves from this sample to the next one
+    /// over in either direction on the screen, which picks the mip level for
+    /// trilinear filtering. Zero means full detail.
+    pub fn sample(&self, uv: DVec2, footprint: [DVec2; 2]) -> DVec3 {
+        match self.filter {
+            TextureFilter::Nearest => self.nearest(0, uv),
+            TextureFilter::Bilinear => self.bilinear(0, uv),
+            TextureFilter::Trilinear => {
+                let size = self.size();
+                let [a, b] = footprint;
+                let (major, minor) = if (a * size).length() >= (b * size).length() {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                let (major_length, minor_length) =
+                    ((major * size).length(), (minor * size).length());
+
+                // Cover a footprint that's much longer than it is wide with
+                // several samples along its length, each only as blurry as
+                // it is wide.
+                let probes = if minor_length > 0.0 {
+                    (major_length / minor_length)
+                        .ceil()
+                        .clamp(1.0, self.anisotropy.max(1) as f64) as u32
+                } else {
+                    1
+                };
+                let lod = (major_length / probes as f64).max(1.0).log2();
+
+                (0..probes)
+                    .map(|i| {
+                        let offset = (i as f64 + 0.5) / probes as f64 - 0.5;
+                        self.trilinear(lod, uv + major * offset)
+                    })
+                    .fold(DVec3::ZERO, |acc, color| acc + color)
+                    / probes as f64
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An 8×8 checkerboard of single white and black texels.
+    fn checkerboard(filter: TextureFilter, anisotropy: u32) -> Texture {
+        let image = ImageBuffer::from_fn(8, 8, |x, y| {
+            image::Rgb([if (x + y) % 2 == 0 { 255 } else { 0 }; 3])
+        });
+        Texture::new(image, filter, WrapMode::Repeat, anisotropy)
+    }
+
+    // The center of the texel in column `x` and row `y` from the bottom of
+    // an 8×8 texture.
+    fn texel_center(x: u32, y: u32) -> DVec2 {
+        (DVec2::new(x as f64, y as f64) + 0.5) / 8.0
+    }
+
+    fn assert_gray(color: DVec3, value: f64) {
+        assert!(
+            (color - DVec3::splat(value)).abs().max_element() < 1e-9,
+            "{}",
+            color
+        );
+    }
+
+    #[test]
+    fn downsampling_averages_blocks_of_four() {
+        let image =
+            ImageBuffer::from_fn(4, 2, |x, y| image::Rgb([(x * 40 + y * 10) as u8, 0, 255]));
+        let half = downsample(&image);
+        assert_eq!(half.dimensions(), (2, 1));
+        // (0 + 40 + 10 + 50) / 4 and (80 + 120 + 90 + 130) / 4
+        assert_eq!(half.get_pixel(0, 0).0, [25, 0, 255]);
+        assert_eq!(half.get_pixel(1, 0).0, [105, 0, 255]);
+    }
+
+    #[test]
+    fn downsampling_odd_sizes_repeats_the_last_row_and_column() {
+        let image = ImageBuffer::from_fn(3, 1, |x, _| image::Rgb([x as u8 * 100; 3]));
+        let half = downsample(&image);
+        assert_eq!(half.dimensions(), (1, 1));
+        // the third column is dropped, and the only row counts twice
+        assert_eq!(half.get_pixel(0, 0).0, [50; 3]);
+    }
+
+    #[test]
+    fn the_mip_chain_goes_down_to_one_texel() {
+        let texture = Texture::new(
+            ImageBuffer::new(5, 3),
+            TextureFilter::Trilinear,
+            WrapMode::Repeat,
+            1,
+        );
+        let sizes: Vec<_> = texture.levels.iter().map(|l| l.dimensions()).collect();
+        assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(checkerboard(TextureFilter::Trilinear, 1).levels.len(), 4);
+    }
+
+    #[test]
+    fn footprints_pick_the_mip_level() {
+        let texture = checkerboard(TextureFilter::Trilinear, 1);
+        let uv = texel_center(2, 3);
+        let footprint =
+            |texels: f64| [DVec2::new(texels / 8.0, 0.0), DVec2::new(0.0, texels / 8.0)];
+
+        // no further than the next texel is full detail
+        assert_gray(texture.sample(uv, [DVec2::ZERO; 2]), 255.0);
+        assert_gray(texture.sample(uv, footprint(1.0)), 255.0);
+        // halfway to the first mip level, where the checks average out
+        assert_gray(texture.sample(uv, footprint(2f64.sqrt())), 191.5);
+        assert_gray(texture.sample(uv, footprint(2.0)), 128.0);
+        assert_gray(texture.sample(uv, footprint(64.0)), 128.0);
+    }
+
+    #[test]
+    fn only_trilinear_filtering_uses_mip_levels() {
+        let uv = texel_center(2, 3);
+        let footprint = [DVec2::new(1.0, 0.0), DVec2::new(0.0, 1.0)];
+        for filter in [TextureFilter::Nearest, TextureFilter::Bilinear] {
+            assert_gray(checkerboard(filter, 1).sample(uv, footprint), 255.0);
+        }
+    }
+
+    #[test]
+    fn anisotropic_footprints_stay_sharp_across_their_width() {
+        // columns alternating white and black, which a footprint running up
+        // a column shouldn't blur together
+        let image = ImageBuffer::from_fn(8, 8, |x, _| {
+            image::Rgb([if x % 2 == 0 { 255 } else { 0 }; 3])
+        });
+        let uv = texel_center(2, 3);
+        let footprint = [DVec2::new(1.0 / 8.0, 0.0), DVec2::new(0.0, 1.0)];
+
+        let anisotropic =
+            Texture::new(image.clone(), TextureFilter::Trilinear, WrapMode::Repeat, 8);
+        assert_gray(anisotropic.sample(uv, footprint), 255.0);
+        let isotropic = Texture::new(image, TextureFilter::Trilinear, WrapMode::Repeat, 1);
+        assert_gray(isotropic.sample(uv, footprint), 128.0);
+    }
+}
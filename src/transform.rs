@@ -1,13 +1,82 @@
+use crate::clip::Plane;
+use crate::light::{AreaShape, Light};
 use crate::scene::{Object, Scene, Triangle};
-use glam::DMat4;
+use crate::section::Sections;
+use glam::{DMat3, DMat4};
 
 impl Triangle {
     pub fn transform(&self, transform_matrix: &DMat4) -> Self {
+        // Normals stay perpendicular to the surface under the inverse
+        // transpose, even when the transform doesn't preserve angles.
+        let normal_matrix = DMat3::from_mat4(*transform_matrix).inverse().transpose();
         Self {
             v0: transform_matrix.transform_point3(self.v0),
             v1: transform_matrix.transform_point3(self.v1),
             v2: transform_matrix.transform_point3(self.v2),
-            color: self.color,
+            normals: self
+                .normals
+                .map(|n| (normal_matrix * n).normalize_or_zero()),
+            uvs: self.uvs,
+            colors: self.colors,
+            edges: self.edges,
+            material: self.material,
+            center: transform_matrix.transform_point3(self.center),
+        }
+    }
+}
+
+impl Light {
+    pub fn transform(&self, transform_matrix: &DMat4) -> Self {
+        let mut light = *self;
+        match &mut light {
+            Self::Ambient { .. } => {}
+            Self::Directional { direction, .. } => {
+                *direction = transform_matrix.transform_vector3(*direction);
+            }
+            Self::Point { position, .. } => {
+                *position = transform_matrix.transform_point3(*position);
+            }
+            Self::Spot {
+                position,
+                direction,
+                ..
+            } => {
+                *position = transform_matrix.transform_point3(*position);
+                *direction = transform_matrix.transform_vector3(*direction);
+            }
+            Self::Area {
+                position, shape, ..
+            } => {
+                *position = transform_matrix.transform_point3(*position);
+                if let AreaShape::Rectangle { u, v } = shape {
+                    *u = transform_matrix.transform_vector3(*u);
+                    *v = transform_matrix.transform_vector3(*v);
+                }
+            }
+        }
+        light
+    }
+}
+
+impl Plane {
+    pub fn transform(&self, transform_matrix: &DMat4) -> Self {
+        let normal_matrix = DMat3::from_mat4(*transform_matrix).inverse().transpose();
+        let normal = (normal_matrix * self.normal).normalize_or_zero();
+        // a point on the plane, which the transform keeps on it
+        let point = transform_matrix.transform_point3(self.normal * -self.d);
+        Self::new(normal, -normal.dot(point))
+    }
+}
+
+impl Sections {
+    pub fn transform(&self, transform_matrix: &DMat4) -> Self {
+        Self {
+            planes: self
+                .planes
+                .iter()
+                .map(|plane| plane.transform(transform_matrix))
+                .collect(),
+            cap: self.cap,
         }
     }
 }
@@ -15,6 +84,13 @@ impl Triangle {
 impl Object {
     pub fn transform(&self, camera_matrix: DMat4) -> Self {
         let transform_matrix = camera_matrix * self.transform;
+        // the bounding sphere grows as much as the transform stretches
+        // anything
+        let linear = DMat3::from_mat4(transform_matrix);
+        let scale = [linear.x_axis, linear.y_axis, linear.z_axis]
+            .map(|axis| axis.length())
+            .into_iter()
+            .fold(0.0, f64::max);
         Self {
             triangles: self
                 .triangles
@@ -23,7 +99,8 @@ impl Object {
                 .collect(),
             transform: self.transform,
             bounding_center: transform_matrix.transform_point3(self.bounding_center),
-            bounding_radius: self.bounding_radius,
+            bounding_radius: self.bounding_radius * scale,
+            sections: self.sections.transform(&camera_matrix),
         }
     }
 }
@@ -31,13 +108,19 @@ impl Object {
 impl Scene {
     pub fn transform(&self) -> Self {
         let camera_matrix = self.camera.transform.inverse();
+        let objects = self
+            .objects
+            .iter()
+            .map(|obj| obj.transform(camera_matrix))
+            .collect();
         Self {
-            objects: self
-                .objects
+            lights: self
+                .lights
                 .iter()
-                .map(|obj| obj.transform(camera_matrix))
+                .map(|light| light.transform(&camera_matrix))
                 .collect(),
-            camera: self.camera,
+            sections: self.sections.transform(&camera_matrix),
+            ..self.with_objects(objects)
         }
     }
 }